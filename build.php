@@ -9,7 +9,7 @@ $content = <<<EOT
 // generated by build.php at $date
 
 use async_std::sync::Arc;
-use core::operator::{http_api, http_server, saga_aggregator, simple_auth, wasm, sql, sql_runner, Monad, Source};
+use core::operator::{http_api, http_server, saga_aggregator, simple_auth, wasm, sql, sql_runner, sql_transaction, cache, cache_invalidator, sql_migrate, sql_crud, sql_query, sql_poller, sql_import, tenant_router, jwt_auth, api_key, basic_auth, rbac, Init, Monad, Source};
 
 #[async_std::main]
 async fn main() {
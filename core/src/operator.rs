@@ -1,10 +1,24 @@
+pub mod api_key;
+pub mod basic_auth;
+pub mod cache;
+pub mod cache_invalidator;
 pub mod http_api;
 pub mod http_server;
+pub mod jwt_auth;
+pub mod rbac;
 pub mod saga_aggregator;
 pub mod simple_auth;
 pub mod sql;
+pub mod sql_crud;
+pub mod sql_import;
+pub mod sql_migrate;
+pub mod sql_poller;
+pub mod sql_query;
 pub mod sql_runner;
+pub mod sql_transaction;
+pub mod tenant_router;
 pub mod wasm;
+mod watched;
 
 use async_std::sync::Weak;
 use async_trait::async_trait;
@@ -21,10 +35,32 @@ pub trait Source: Sync {
     async fn start(&self) -> Result<(), OperatorError>;
 }
 
+/// One-off startup work, run once the graph is wired and before any `Source` starts.
+#[async_trait]
+pub trait Init: Sync {
+    async fn init(&self) -> Result<(), OperatorError>;
+}
+
+/// Takes the rows emitted by a change source such as `sql_poller`, an error makes the
+/// source emit the row again later.
+#[async_trait]
+pub trait Sink: Send + Sync {
+    async fn emit(&self, row: &serde_json::Value) -> Result<(), OperatorError>;
+}
+
 impl<T: Source> Operator for T {}
 
 pub trait Operator {}
 
+/// Identity attached to a request (via `Request::set_ext`) by an auth operator,
+/// so that downstream operators can bind or authorize on it.
+#[derive(Clone, Debug, Default)]
+pub struct Principal {
+    pub name: String,
+    pub roles: Vec<String>,
+    pub claims: serde_json::Map<String, serde_json::Value>,
+}
+
 pub trait Monad<O>
 where
     O: Operator,
@@ -0,0 +1,473 @@
+use super::simple_auth::constant_time_eq;
+use super::sql::Sql;
+use super::sql_runner::{Backend, DateTimeFormat, Format, SqlRunner};
+use super::{OperatorError, Principal};
+use async_std::stream::StreamExt;
+use async_std::sync::{Arc, RwLock, Weak};
+use async_std::task;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
+use sqlx::mysql::MySql;
+use sqlx::postgres::Postgres;
+use sqlx::sqlite::Sqlite;
+use sqlx::Executor;
+use std::time::Duration;
+use thiserror::Error;
+use tide::{Request, StatusCode};
+
+/// Checks the API key of a request against keys stored as their sha256, from the config,
+/// a file and a table, and turns the matching key into a `Principal` with its scopes as roles.
+///
+/// The file and the table are read at startup and then every `refresh_interval`, a failed
+/// refresh keeps the keys loaded before.
+pub struct ApiKey {
+    w: WType,
+    config: Config,
+    keys: RwLock<Vec<Entry>>,
+}
+
+enum WType {
+    Mysql(Weak<Sql<MySql>>),
+    Sqlite(Weak<Sql<Sqlite>>),
+    Postgres(Weak<Sql<Postgres>>),
+    None,
+}
+
+struct Entry {
+    name: String,
+    hash: String,
+    scopes: Vec<String>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct Config {
+    /// header holding the key, `X-Api-Key` by default
+    #[serde(default)]
+    pub header: Option<String>,
+    #[serde(default)]
+    pub keys: Vec<Key>,
+    /// a json array of keys
+    #[serde(default)]
+    pub file: Option<String>,
+    /// table of the applied sql, with `name`, `hash`, `scopes` (space separated) and
+    /// `expires_at` columns
+    #[serde(default)]
+    pub table: Option<String>,
+    /// seconds between reloads of `file` and `table`, read once when unset
+    #[serde(default)]
+    pub refresh_interval: Option<u64>,
+    /// scopes a key needs for the requests of this operator
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// logs the key name of each request
+    #[serde(default)]
+    pub log: bool,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Key {
+    pub name: String,
+    /// hex sha256 of the key
+    pub hash: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// RFC 3339, never expires when unset
+    #[serde(default)]
+    pub expires_at: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum ApiKeyError {
+    #[error("api key is missing")]
+    Missing,
+    #[error("invalid api key")]
+    Invalid,
+    #[error("api key `{0}` is expired")]
+    Expired(String),
+    #[error("api key `{0}` lacks scope `{1}`")]
+    Scope(String, String),
+    #[error("sql is not applied")]
+    NotApplied,
+    #[error("invalid key `{0}`: {1}")]
+    Key(String, String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Sql(#[from] sqlx::Error),
+}
+
+impl ApiKeyError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiKeyError::Missing | ApiKeyError::Invalid | ApiKeyError::Expired(_) => {
+                StatusCode::Unauthorized
+            }
+            ApiKeyError::Scope(..) => StatusCode::Forbidden,
+            _ => StatusCode::InternalServerError,
+        }
+    }
+}
+
+const DEFAULT_HEADER: &str = "X-Api-Key";
+
+impl ApiKey {
+    pub fn new(config: Config) -> Self {
+        if let Some(table) = &config.table {
+            if !table
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+            {
+                panic!("invalid api_key table: {}", table);
+            }
+        }
+        let keys = config
+            .keys
+            .iter()
+            .map(Entry::from_key)
+            .collect::<Result<Vec<_>, _>>();
+        let keys = match keys {
+            Ok(keys) => keys,
+            Err(err) => panic!("invalid api_key config: {}", err),
+        };
+
+        ApiKey {
+            w: WType::None,
+            config,
+            keys: RwLock::new(keys),
+        }
+    }
+
+    /// The principal of the key of `req`, which has every scope of the config.
+    pub async fn auth(&self, req: &Request<()>) -> Result<Principal, ApiKeyError> {
+        let header = self.config.header.as_deref().unwrap_or(DEFAULT_HEADER);
+        let key = req.header(header).ok_or(ApiKeyError::Missing)?;
+        let principal = self.check(key.as_str()).await?;
+        if self.config.log {
+            log::info!(
+                "api_key {}: {} {}",
+                principal.name,
+                req.method(),
+                req.url().path()
+            );
+        }
+        Ok(principal)
+    }
+
+    pub async fn check(&self, key: &str) -> Result<Principal, ApiKeyError> {
+        let hash = format!("{:x}", Sha256::digest(key.as_bytes()));
+        let keys = self.keys.read().await;
+        // every entry is compared, so the time taken doesn't tell which one matched
+        let mut found = None;
+        for entry in keys.iter() {
+            if constant_time_eq(entry.hash.as_bytes(), hash.as_bytes()) {
+                found = Some(entry);
+            }
+        }
+        let entry = found.ok_or(ApiKeyError::Invalid)?;
+
+        if matches!(entry.expires_at, Some(at) if at <= Utc::now()) {
+            return Err(ApiKeyError::Expired(entry.name.clone()));
+        }
+        if let Some(scope) = self
+            .config
+            .scopes
+            .iter()
+            .find(|s| !entry.scopes.contains(s))
+        {
+            return Err(ApiKeyError::Scope(entry.name.clone(), scope.clone()));
+        }
+
+        let mut claims = serde_json::Map::new();
+        claims.insert("key".to_string(), JsonValue::from(entry.name.clone()));
+        Ok(Principal {
+            name: entry.name.clone(),
+            roles: entry.scopes.clone(),
+            claims,
+        })
+    }
+
+    /// Reads the keys of the config, the file and the table, and replaces the loaded ones.
+    /// An invalid key of the file or the table is logged and skipped, the others still load.
+    pub async fn load(&self) -> Result<usize, ApiKeyError> {
+        let mut keys = self
+            .config
+            .keys
+            .iter()
+            .map(Entry::from_key)
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(file) = &self.config.file {
+            let file: Vec<JsonValue> = serde_json::from_slice(&std::fs::read(file)?)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            for (i, key) in file.into_iter().enumerate() {
+                let entry = serde_json::from_value::<Key>(key)
+                    .map_err(|e| ApiKeyError::Key(format!("#{}", i), e.to_string()))
+                    .and_then(|key| Entry::from_key(&key));
+                keys.extend(skip_invalid(entry));
+            }
+        }
+        if let Some(table) = &self.config.table {
+            let rows = match &self.w {
+                WType::Mysql(w) => select(upgrade(w)?, table).await?,
+                WType::Sqlite(w) => select(upgrade(w)?, table).await?,
+                WType::Postgres(w) => select(upgrade(w)?, table).await?,
+                WType::None => return Err(ApiKeyError::NotApplied),
+            };
+            for row in rows {
+                keys.extend(skip_invalid(Entry::from_row(row)));
+            }
+        }
+
+        let n = keys.len();
+        *self.keys.write().await = keys;
+        Ok(n)
+    }
+}
+
+impl Entry {
+    fn from_key(key: &Key) -> Result<Entry, ApiKeyError> {
+        let expires_at = match &key.expires_at {
+            Some(at) => Some(
+                DateTime::parse_from_rfc3339(at)
+                    .map_err(|e| ApiKeyError::Key(key.name.clone(), e.to_string()))?
+                    .with_timezone(&Utc),
+            ),
+            None => None,
+        };
+        Entry::new(&key.name, &key.hash, key.scopes.clone(), expires_at)
+    }
+
+    // `expires_at` is epoch seconds for a datetime column, or the text of a text one
+    fn from_row(row: JsonValue) -> Result<Entry, ApiKeyError> {
+        let text = |column: &str| row[column].as_str().unwrap_or_default().to_string();
+        let name = text("name");
+        let scopes = text("scopes")
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+        let expires_at = match &row["expires_at"] {
+            JsonValue::Null => None,
+            JsonValue::Number(n) => n.as_i64().map(|s| Utc.timestamp(s, 0)),
+            JsonValue::String(s) => DateTime::parse_from_rfc3339(s)
+                .map(|at| at.with_timezone(&Utc))
+                .or_else(|_| {
+                    NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")
+                        .map(|at| Utc.from_utc_datetime(&at))
+                })
+                .map(Some)
+                .map_err(|e| ApiKeyError::Key(name.clone(), e.to_string()))?,
+            v => return Err(ApiKeyError::Key(name, format!("expires_at {}", v))),
+        };
+        Entry::new(&name, &text("hash"), scopes, expires_at)
+    }
+
+    fn new(
+        name: &str,
+        hash: &str,
+        scopes: Vec<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<Entry, ApiKeyError> {
+        let hash = hash.to_lowercase();
+        if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ApiKeyError::Key(
+                name.to_string(),
+                "hash is not a hex sha256".to_string(),
+            ));
+        }
+        Ok(Entry {
+            name: name.to_string(),
+            hash,
+            scopes,
+            expires_at,
+        })
+    }
+}
+
+async fn select<DB: Backend>(a: Arc<Sql<DB>>, table: &str) -> Result<Vec<JsonValue>, ApiKeyError>
+where
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+{
+    let format = Format {
+        datetime: DateTimeFormat::EpochSeconds,
+        ..Default::default()
+    };
+    let sql = format!("select name, hash, scopes, expires_at from {}", table);
+    let mut con = a.get_reader().await?;
+    let mut cursor = a.call(sqlx::query(sql.as_str()), &mut *con).await;
+    let mut rows = vec![];
+    while let Some(row) = cursor.next().await {
+        rows.push(SqlRunner::row_to_json::<DB>(&row?, &format)?);
+    }
+    Ok(rows)
+}
+
+fn skip_invalid(entry: Result<Entry, ApiKeyError>) -> Option<Entry> {
+    match entry {
+        Ok(entry) => Some(entry),
+        Err(err) => {
+            log::warn!("api_key skipped: {}", err);
+            None
+        }
+    }
+}
+
+fn upgrade<DB: sqlx::Database>(w: &Weak<Sql<DB>>) -> Result<Arc<Sql<DB>>, ApiKeyError> {
+    w.upgrade().ok_or(ApiKeyError::NotApplied)
+}
+
+#[async_trait]
+impl super::Init for ApiKey {
+    async fn init(&self) -> Result<(), OperatorError> {
+        self.load()
+            .await
+            .map(|_| ())
+            .map_err(|e| OperatorError::Other(e.into()))
+    }
+}
+
+#[async_trait]
+impl super::Source for ApiKey {
+    async fn start(&self) -> Result<(), OperatorError> {
+        let interval = match self.config.refresh_interval {
+            Some(interval) if self.config.file.is_some() || self.config.table.is_some() => {
+                Duration::from_secs(interval)
+            }
+            _ => return Ok(()),
+        };
+        loop {
+            task::sleep(interval).await;
+            if let Err(err) = self.load().await {
+                log::error!("api_key refresh faild: {}", err);
+            }
+        }
+    }
+}
+
+impl super::Monad<Sql<MySql>> for ApiKey {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<Sql<MySql>>) -> Self::Result {
+        self.w = WType::Mysql(w)
+    }
+}
+
+impl super::Monad<Sql<Sqlite>> for ApiKey {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<Sql<Sqlite>>) -> Self::Result {
+        self.w = WType::Sqlite(w)
+    }
+}
+
+impl super::Monad<Sql<Postgres>> for ApiKey {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<Sql<Postgres>>) -> Self::Result {
+        self.w = WType::Postgres(w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::sql;
+    use super::*;
+    use crate::operator::Monad;
+
+    fn hash(key: &str) -> String {
+        format!("{:x}", Sha256::digest(key.as_bytes()))
+    }
+
+    #[async_std::test]
+    async fn check() {
+        let auth = ApiKey::new(Config {
+            keys: vec![
+                Key {
+                    name: "ci".to_string(),
+                    hash: hash("k1"),
+                    scopes: vec!["read".to_string(), "write".to_string()],
+                    ..Default::default()
+                },
+                Key {
+                    name: "old".to_string(),
+                    hash: hash("k2"),
+                    scopes: vec!["write".to_string()],
+                    expires_at: Some("2020-01-01T00:00:00Z".to_string()),
+                },
+                Key {
+                    name: "reader".to_string(),
+                    hash: hash("k3"),
+                    scopes: vec!["read".to_string()],
+                    ..Default::default()
+                },
+            ],
+            scopes: vec!["write".to_string()],
+            ..Default::default()
+        });
+
+        let principal = auth.check("k1").await.unwrap();
+        assert_eq!(principal.name, "ci");
+        assert_eq!(principal.roles, vec!["read", "write"]);
+        assert!(matches!(
+            auth.check("k2").await,
+            Err(ApiKeyError::Expired(_))
+        ));
+        assert!(matches!(
+            auth.check("k3").await,
+            Err(ApiKeyError::Scope(..))
+        ));
+        assert!(matches!(auth.check("k4").await, Err(ApiKeyError::Invalid)));
+    }
+
+    #[async_std::test]
+    async fn load() {
+        let sql = Arc::new(
+            sql::Sql::<Sqlite>::new(sql::Config {
+                dsn: "sqlite::memory:".to_string(),
+                ..Default::default()
+            })
+            .await,
+        );
+        let mut con = sql.get_executor().await.unwrap();
+        sqlx::query("create table api_keys (name text, hash text, scopes text, expires_at text)")
+            .execute(&mut con)
+            .await
+            .unwrap();
+        sqlx::query("insert into api_keys values (?, ?, 'read write', '2999-01-01 00:00:00')")
+            .bind("db")
+            .bind(hash("k1"))
+            .execute(&mut con)
+            .await
+            .unwrap();
+        // skipped, without failing the rest
+        sqlx::query("insert into api_keys values ('bad', 'not a hash', '', null)")
+            .execute(&mut con)
+            .await
+            .unwrap();
+        drop(con);
+
+        let path = std::env::temp_dir().join("api_keys.json");
+        let file = serde_json::json!([
+            {"name": "file", "hash": hash("k2")},
+            {"name": "no hash"},
+            {"name": "bad date", "hash": hash("k3"), "expires_at": "tomorrow"},
+        ]);
+        std::fs::write(&path, serde_json::to_vec(&file).unwrap()).unwrap();
+
+        let mut auth = ApiKey::new(Config {
+            file: Some(path.to_string_lossy().to_string()),
+            table: Some("api_keys".to_string()),
+            ..Default::default()
+        });
+        auth.apply(Arc::downgrade(&sql));
+        assert!(auth.check("k1").await.is_err());
+
+        assert_eq!(auth.load().await.unwrap(), 2);
+        let principal = auth.check("k1").await.unwrap();
+        assert_eq!(principal.name, "db");
+        assert_eq!(principal.roles, vec!["read", "write"]);
+        assert_eq!(auth.check("k2").await.unwrap().name, "file");
+    }
+}
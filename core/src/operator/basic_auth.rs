@@ -0,0 +1,216 @@
+use super::watched::Watched;
+use super::Principal;
+use async_std::task;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+use tide::{Request, StatusCode};
+
+/// Checks `Authorization: Basic` credentials against an htpasswd file of bcrypt or argon2
+/// hashes, read again whenever it changes.
+pub struct BasicAuth {
+    config: Config,
+    // hashes by user
+    users: Watched<HashMap<String, String>>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct Config {
+    /// lines of `user:hash`, made with `htpasswd -B` or an argon2 encoder
+    pub file: String,
+    /// realm of the `WWW-Authenticate` challenge, `restricted` by default
+    #[serde(default)]
+    pub realm: Option<String>,
+    /// roles of the principal of each user
+    #[serde(default)]
+    pub roles: HashMap<String, Vec<String>>,
+}
+
+#[derive(Error, Debug)]
+pub enum BasicAuthError {
+    #[error("credentials are missing")]
+    Missing,
+    #[error("invalid credentials")]
+    Invalid,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl BasicAuthError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            BasicAuthError::Io(_) => StatusCode::InternalServerError,
+            _ => StatusCode::Unauthorized,
+        }
+    }
+}
+
+const AUTHKEY: &str = "Authorization";
+const DEFAULT_REALM: &str = "restricted";
+
+impl super::Operator for BasicAuth {}
+
+impl BasicAuth {
+    pub fn new(config: Config) -> Self {
+        let users = match Watched::new(&config.file, read) {
+            Ok(users) => users,
+            Err(err) => panic!("invalid basic_auth config: {}: {}", config.file, err),
+        };
+
+        BasicAuth { config, users }
+    }
+
+    /// The value of the `WWW-Authenticate` header of a 401.
+    pub fn challenge(&self) -> String {
+        let realm = self.config.realm.as_deref().unwrap_or(DEFAULT_REALM);
+        format!(
+            "Basic realm=\"{}\", charset=\"UTF-8\"",
+            realm.replace('"', "")
+        )
+    }
+
+    pub async fn auth(&self, req: &Request<()>) -> Result<Principal, BasicAuthError> {
+        let header = req.header(AUTHKEY).ok_or(BasicAuthError::Missing)?;
+        let (user, password) = credentials(header.as_str()).ok_or(BasicAuthError::Missing)?;
+        self.check(&user, &password).await
+    }
+
+    pub async fn check(&self, user: &str, password: &str) -> Result<Principal, BasicAuthError> {
+        // an unknown user is verified against the hash of another one, so the time taken
+        // doesn't tell which users exist; without any user there is nothing to tell
+        let (hash, known) = {
+            let users = self.users.get().await;
+            match users.get(user) {
+                Some(hash) => (hash.clone(), true),
+                None => match users.values().next() {
+                    Some(hash) => (hash.clone(), false),
+                    None => return Err(BasicAuthError::Invalid),
+                },
+            }
+        };
+
+        // hashing takes long by design, so it is kept off the async threads
+        let password = password.to_string();
+        let verified = task::spawn_blocking(move || verify(&hash, &password)).await;
+        if !known || !verified {
+            return Err(BasicAuthError::Invalid);
+        }
+        Ok(Principal {
+            name: user.to_string(),
+            roles: self.config.roles.get(user).cloned().unwrap_or_default(),
+            claims: serde_json::Map::new(),
+        })
+    }
+}
+
+fn read(bytes: &[u8]) -> std::io::Result<HashMap<String, String>> {
+    let text = std::str::from_utf8(bytes)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    Ok(parse(text))
+}
+
+// blank and `#` lines are skipped, as are hashes of other schemes
+fn parse(text: &str) -> HashMap<String, String> {
+    let mut hashes = HashMap::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(user), Some(hash)) if supported(hash) => {
+                hashes.insert(user.to_string(), hash.to_string());
+            }
+            (Some(user), Some(_)) => log::warn!("basic_auth: unsupported hash of `{}`", user),
+            _ => {}
+        }
+    }
+    hashes
+}
+
+fn supported(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2y$", "$argon2"]
+        .iter()
+        .any(|p| hash.starts_with(p))
+}
+
+fn verify(hash: &str, password: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        argon2::verify_encoded(hash, password.as_bytes()).unwrap_or(false)
+    } else {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    }
+}
+
+fn credentials(header: &str) -> Option<(String, String)> {
+    let encoded = header.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(base64::decode(encoded.trim()).ok()?).ok()?;
+    let mut parts = decoded.splitn(2, ':');
+    Some((parts.next()?.to_string(), parts.next()?.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header() {
+        let header = format!("Basic {}", base64::encode("alice:pa:ss"));
+        assert_eq!(
+            credentials(&header),
+            Some(("alice".to_string(), "pa:ss".to_string()))
+        );
+        assert_eq!(credentials("Bearer abc"), None);
+        assert_eq!(credentials("Basic !!"), None);
+    }
+
+    #[async_std::test]
+    async fn check() {
+        let bcrypt = bcrypt::hash("secret", 4).unwrap();
+        let argon2 =
+            argon2::hash_encoded(b"secret", b"somesalt", &argon2::Config::default()).unwrap();
+        let path = std::env::temp_dir().join("basic_auth.htpasswd");
+        let file = path.to_string_lossy().to_string();
+        std::fs::write(
+            &path,
+            format!(
+                "# users\nalice:{}\nbob:{}\ncarol:{{SHA}}x\n",
+                bcrypt, argon2
+            ),
+        )
+        .unwrap();
+
+        let mut roles = HashMap::new();
+        roles.insert("alice".to_string(), vec!["admin".to_string()]);
+        let auth = BasicAuth::new(Config {
+            file: file.clone(),
+            roles,
+            ..Default::default()
+        });
+
+        let principal = auth.check("alice", "secret").await.unwrap();
+        assert_eq!(principal.roles, vec!["admin"]);
+        assert_eq!(auth.check("bob", "secret").await.unwrap().name, "bob");
+        assert!(matches!(
+            auth.check("alice", "wrong").await,
+            Err(BasicAuthError::Invalid)
+        ));
+        assert!(matches!(
+            auth.check("carol", "x").await,
+            Err(BasicAuthError::Invalid)
+        ));
+        // the password of another user doesn't pass for an unknown one
+        assert!(matches!(
+            auth.check("dave", "secret").await,
+            Err(BasicAuthError::Invalid)
+        ));
+
+        // read again once changed
+        std::fs::write(&path, format!("bob:{}\n", argon2)).unwrap();
+        assert!(auth.check("alice", "secret").await.is_err());
+        assert_eq!(
+            auth.challenge(),
+            "Basic realm=\"restricted\", charset=\"UTF-8\""
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use super::saga_aggregator::SagaAggregator;
+use super::sql_runner::SqlRunner;
+use super::OperatorError;
+use async_std::sync::{Arc, Mutex, Weak};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tide::http::Mime;
+use tide::{Body, Request, Result, StatusCode};
+
+/// Caches the responses of a handler, keyed on the route and the bound params.
+/// Concurrent misses of the same key wait for a single call of the handler.
+pub struct Cache {
+    w: WType,
+    config: Config,
+    state: Mutex<State>,
+    // never held across an await, so a dropped call can clean up in `drop`
+    flights: std::sync::Mutex<HashMap<String, Arc<Mutex<()>>>>,
+}
+
+// The call of the handler for a key, whose flight is removed however the call ends,
+// including when its future is dropped.
+struct Leader<'a> {
+    flights: &'a std::sync::Mutex<HashMap<String, Arc<Mutex<()>>>>,
+    key: &'a str,
+    flight: &'a Arc<Mutex<()>>,
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        let mut flights = self.flights.lock().unwrap();
+        if matches!(flights.get(self.key), Some(f) if Arc::ptr_eq(f, self.flight)) {
+            flights.remove(self.key);
+        }
+    }
+}
+
+enum WType {
+    SqlRunner(Weak<SqlRunner>),
+    SagaAggregator(Weak<SagaAggregator>),
+    None,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct Config {
+    /// seconds an entry is served before the handler is called again
+    pub ttl: u64,
+    pub max_entries: usize,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<String, Entry>,
+    // last use of each key, the first one is evicted when full
+    lru: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+struct Entry {
+    value: Cached,
+    expires: Instant,
+    tick: u64,
+}
+
+#[derive(Clone)]
+pub struct Cached {
+    pub bytes: Vec<u8>,
+    pub mime: Mime,
+}
+
+impl State {
+    fn get(&mut self, key: &str, now: Instant) -> Option<Cached> {
+        let (value, old) = match self.entries.get_mut(key) {
+            Some(e) if e.expires > now => {
+                self.tick += 1;
+                let old = std::mem::replace(&mut e.tick, self.tick);
+                (Some(e.value.clone()), old)
+            }
+            Some(e) => (None, e.tick),
+            None => return None,
+        };
+
+        self.lru.remove(&old);
+        match value {
+            Some(_) => {
+                self.lru.insert(self.tick, key.to_string());
+            }
+            None => {
+                self.entries.remove(key);
+            }
+        }
+        value
+    }
+
+    fn insert(&mut self, key: String, value: Cached, expires: Instant, max_entries: usize) {
+        if let Some(e) = self.entries.remove(&key) {
+            self.lru.remove(&e.tick);
+        }
+        while self.entries.len() >= max_entries.max(1) {
+            let oldest = match self.lru.keys().next() {
+                Some(tick) => *tick,
+                None => break,
+            };
+            if let Some(k) = self.lru.remove(&oldest) {
+                self.entries.remove(&k);
+            }
+        }
+
+        self.tick += 1;
+        self.lru.insert(self.tick, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                expires,
+                tick: self.tick,
+            },
+        );
+    }
+
+    fn purge(&mut self, prefix: &str) {
+        let lru = &mut self.lru;
+        self.entries.retain(|key, e| {
+            let keep = !under(key, prefix);
+            if !keep {
+                lru.remove(&e.tick);
+            }
+            keep
+        });
+    }
+}
+
+impl super::Operator for Cache {}
+
+impl Cache {
+    pub fn new(config: Config) -> Self {
+        if config.max_entries == 0 {
+            panic!("invalid cache config: max_entries must be positive");
+        }
+
+        Cache {
+            w: WType::None,
+            config,
+            state: Mutex::new(State::default()),
+            flights: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn handle(&self, mut req: Request<()>) -> Result<Body> {
+        let route = req.url().path().to_string();
+        let cached = match &self.w {
+            WType::SqlRunner(w) => {
+                let a = w.upgrade().ok_or_else(not_applied)?;
+                let export = a.export(&req);
+                let input = a.input(&mut req).await?;
+                let args = a
+                    .args(&input)
+                    .map_err(|e| tide::Error::new(StatusCode::BadRequest, e))?;
+                // a caller allowed to see masked columns, or of another tenant, gets its
+                // own entries
+                let key = format!(
+                    "{} {:?} {} {:?} {:?}",
+                    route,
+                    args,
+                    a.masked(&input),
+                    export,
+                    input.tenant
+                );
+                self.get_or_insert(key, || async {
+                    to_cached(a.respond_as(&input, export).await?).await
+                })
+                .await?
+            }
+            WType::SagaAggregator(w) => {
+                let a = w.upgrade().ok_or_else(not_applied)?;
+                let body = req.body_string().await?;
+                let key = format!("{} {}", route, body);
+                req.set_body(body);
+                self.get_or_insert(key, || async move { to_cached(a.handle(req).await?).await })
+                    .await?
+            }
+            WType::None => return Err(not_applied()),
+        };
+
+        let mut body = Body::from(cached.bytes);
+        body.set_mime(cached.mime);
+        Ok(body)
+    }
+
+    /// Returns the live entry of `key` or stores the result of `f`, errors are not cached.
+    pub async fn get_or_insert<F, Fut>(&self, key: String, f: F) -> Result<Cached>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Cached>>,
+    {
+        loop {
+            if let Some(v) = self.state.lock().await.get(&key, Instant::now()) {
+                return Ok(v);
+            }
+
+            let flight = self
+                .flights
+                .lock()
+                .unwrap()
+                .entry(key.clone())
+                .or_insert_with(|| Arc::new(Mutex::new(())))
+                .clone();
+            let _guard = flight.lock().await;
+
+            // the flight waited for ended and was removed, another one may have started
+            let current = match self.flights.lock().unwrap().get(&key) {
+                Some(f) => Arc::ptr_eq(f, &flight),
+                None => false,
+            };
+            if !current {
+                continue;
+            }
+            // filled by the call this one waited for
+            if let Some(v) = self.state.lock().await.get(&key, Instant::now()) {
+                return Ok(v);
+            }
+
+            // dropped before `_guard`, so no waiter takes over a flight about to be removed
+            let _leader = Leader {
+                flights: &self.flights,
+                key: &key,
+                flight: &flight,
+            };
+            let result = f().await;
+            if let Ok(v) = &result {
+                let expires = Instant::now() + Duration::from_secs(self.config.ttl);
+                self.state.lock().await.insert(
+                    key.clone(),
+                    v.clone(),
+                    expires,
+                    self.config.max_entries,
+                );
+            }
+            return result;
+        }
+    }
+
+    /// Drops every entry of the route `prefix` and the routes below it, so `/a` drops `/a`
+    /// and `/a/1` but not `/ab`; an empty prefix clears the cache.
+    pub async fn invalidate(&self, prefix: &str) {
+        self.state.lock().await.purge(prefix);
+    }
+}
+
+// keys start with the route and a space
+fn under(key: &str, prefix: &str) -> bool {
+    match key.strip_prefix(prefix) {
+        Some(rest) => {
+            prefix.is_empty() || prefix.ends_with('/') || rest.starts_with(&[' ', '/'][..])
+        }
+        None => false,
+    }
+}
+
+async fn to_cached(body: Body) -> Result<Cached> {
+    let mime = body.mime().clone();
+    Ok(Cached {
+        bytes: body.into_bytes().await?,
+        mime,
+    })
+}
+
+fn not_applied() -> tide::Error {
+    tide::Error::from_str(StatusCode::InternalServerError, "cache is not applied")
+}
+
+/// A changed row purges every entry, since any of them may include it.
+#[async_trait]
+impl super::Sink for Cache {
+    async fn emit(&self, _: &serde_json::Value) -> std::result::Result<(), OperatorError> {
+        self.invalidate("").await;
+        Ok(())
+    }
+}
+
+impl super::Monad<SqlRunner> for Cache {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<SqlRunner>) -> Self::Result {
+        self.w = WType::SqlRunner(w);
+    }
+}
+
+impl super::Monad<SagaAggregator> for Cache {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<SagaAggregator>) -> Self::Result {
+        self.w = WType::SagaAggregator(w);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn cached(s: &str) -> Cached {
+        Cached {
+            bytes: s.as_bytes().to_vec(),
+            mime: tide::http::mime::PLAIN,
+        }
+    }
+
+    #[async_std::test]
+    async fn hit_evict_invalidate() {
+        let cache = Cache::new(Config {
+            ttl: 60,
+            max_entries: 2,
+        });
+        let calls = AtomicUsize::new(0);
+        let get = |key: &str| {
+            let key = key.to_string();
+            let calls = &calls;
+            let cache = &cache;
+            async move {
+                cache
+                    .get_or_insert(key.clone(), || async move {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        Ok(cached(key.as_str()))
+                    })
+                    .await
+                    .unwrap()
+                    .bytes
+            }
+        };
+
+        assert_eq!(get("/a 1").await, b"/a 1");
+        assert_eq!(get("/a 1").await, b"/a 1");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // "/a 1" was used last, so "/b 1" is evicted
+        get("/b 1").await;
+        get("/a 1").await;
+        get("/c 1").await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        get("/a 1").await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        get("/b 1").await;
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        cache.invalidate("/a").await;
+        get("/a 1").await;
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn route_boundary() {
+        assert!(under("/a 1", "/a"));
+        assert!(under("/a/1 []", "/a"));
+        assert!(under("/a/1 []", "/a/"));
+        assert!(under("/ab 1", ""));
+        assert!(!under("/ab 1", "/a"));
+        assert!(!under("/b 1", "/a"));
+    }
+
+    #[async_std::test]
+    async fn single_flight() {
+        let cache = Arc::new(Cache::new(Config {
+            ttl: 60,
+            max_entries: 10,
+        }));
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                let calls = calls.clone();
+                async_std::task::spawn(async move {
+                    cache
+                        .get_or_insert("/slow".to_string(), || async move {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                            async_std::task::sleep(Duration::from_millis(50)).await;
+                            Ok(cached("slow"))
+                        })
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect();
+        for h in handles {
+            assert_eq!(h.await.bytes, b"slow");
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[async_std::test]
+    async fn dropped_leader() {
+        let cache = Cache::new(Config {
+            ttl: 60,
+            max_entries: 10,
+        });
+        // the caller goes away while the handler runs
+        let slow = cache.get_or_insert("/slow".to_string(), || async {
+            async_std::task::sleep(Duration::from_secs(10)).await;
+            Ok(cached("slow"))
+        });
+        assert!(async_std::future::timeout(Duration::from_millis(50), slow)
+            .await
+            .is_err());
+        assert!(cache.flights.lock().unwrap().is_empty());
+
+        let v = cache
+            .get_or_insert("/slow".to_string(), || async { Ok(cached("fast")) })
+            .await
+            .unwrap();
+        assert_eq!(v.bytes, b"fast");
+        assert!(cache.flights.lock().unwrap().is_empty());
+    }
+
+    #[async_std::test]
+    async fn expired() {
+        let cache = Cache::new(Config {
+            ttl: 0,
+            max_entries: 10,
+        });
+        let calls = AtomicUsize::new(0);
+        for _ in 0..2 {
+            cache
+                .get_or_insert("/a".to_string(), || async {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    Ok(cached("a"))
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,93 @@
+use super::cache::Cache;
+use super::sql_runner::SqlRunner;
+use super::sql_transaction::SqlTransaction;
+use async_std::sync::Weak;
+use serde::{Deserialize, Serialize};
+use tide::{Body, Request, Result, StatusCode};
+
+/// Wraps a write handler and purges the applied caches after it succeeds.
+pub struct CacheInvalidator {
+    w: WType,
+    config: Config,
+    caches: Vec<Weak<Cache>>,
+}
+
+enum WType {
+    SqlRunner(Weak<SqlRunner>),
+    SqlTransaction(Weak<SqlTransaction>),
+    None,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct Config {
+    /// route prefixes to purge, all entries when empty
+    #[serde(default)]
+    pub routes: Vec<String>,
+}
+
+impl super::Operator for CacheInvalidator {}
+
+impl CacheInvalidator {
+    pub fn new(config: Config) -> Self {
+        CacheInvalidator {
+            w: WType::None,
+            config,
+            caches: vec![],
+        }
+    }
+
+    pub async fn handle(&self, req: Request<()>) -> Result<Body> {
+        let body = match &self.w {
+            WType::SqlRunner(w) => match w.upgrade() {
+                Some(a) => a.handle(req).await?,
+                None => return Err(not_applied()),
+            },
+            WType::SqlTransaction(w) => match w.upgrade() {
+                Some(a) => a.handle(req).await?,
+                None => return Err(not_applied()),
+            },
+            WType::None => return Err(not_applied()),
+        };
+
+        for cache in self.caches.iter().filter_map(Weak::upgrade) {
+            if self.config.routes.is_empty() {
+                cache.invalidate("").await;
+            }
+            for route in &self.config.routes {
+                cache.invalidate(route.as_str()).await;
+            }
+        }
+        Ok(body)
+    }
+}
+
+fn not_applied() -> tide::Error {
+    tide::Error::from_str(
+        StatusCode::InternalServerError,
+        "cache_invalidator is not applied",
+    )
+}
+
+impl super::Monad<SqlRunner> for CacheInvalidator {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<SqlRunner>) -> Self::Result {
+        self.w = WType::SqlRunner(w);
+    }
+}
+
+impl super::Monad<SqlTransaction> for CacheInvalidator {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<SqlTransaction>) -> Self::Result {
+        self.w = WType::SqlTransaction(w);
+    }
+}
+
+impl super::Monad<Cache> for CacheInvalidator {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<Cache>) -> Self::Result {
+        self.caches.push(w);
+    }
+}
@@ -1,25 +1,49 @@
+use super::api_key::ApiKey;
+use super::basic_auth::BasicAuth;
+use super::cache::Cache;
+use super::cache_invalidator::CacheInvalidator;
+use super::jwt_auth::JwtAuth;
+use super::rbac::Rbac;
 use super::saga_aggregator::SagaAggregator;
 use super::simple_auth::SimpleAuth;
+use super::sql_import::SqlImport;
+use super::sql_query::SqlQuery;
 use super::sql_runner::SqlRunner;
+use super::sql_transaction::SqlTransaction;
 use async_std::sync::Weak;
 use serde::{Deserialize, Serialize};
 
-use tide::{Body, Request, Result};
+use super::Monad;
+use tide::{Body, Request, Response, Result, StatusCode};
 
 pub struct HTTPAPI {
     config: Config,
     w: WType,
-    auth: AuthType,
+    guard: Guard,
 }
 
 enum AuthType {
     SimpleAuth(Weak<SimpleAuth>),
+    Jwt(Weak<JwtAuth>),
+    ApiKey(Weak<ApiKey>),
+    Basic(Weak<BasicAuth>),
     None,
 }
 
+impl Default for AuthType {
+    fn default() -> Self {
+        AuthType::None
+    }
+}
+
 enum WType {
     SqlRunner(Weak<SqlRunner>),
     SagaAggregator(Weak<SagaAggregator>),
+    SqlTransaction(Weak<SqlTransaction>),
+    Cache(Weak<Cache>),
+    CacheInvalidator(Weak<CacheInvalidator>),
+    SqlQuery(Weak<SqlQuery>),
+    SqlImport(Weak<SqlImport>),
     None,
 }
 
@@ -35,17 +59,19 @@ pub enum Method {
     POST,
     PUT,
     DELETE,
+    PATCH,
     ALL,
 }
 
 const AUTHKEY: &str = "Authorization";
+const CHALLENGEKEY: &str = "WWW-Authenticate";
 
 impl HTTPAPI {
     pub fn new(config: Config) -> Self {
         Self {
             config,
             w: WType::None,
-            auth: AuthType::None,
+            guard: Guard::default(),
         }
     }
 
@@ -53,7 +79,76 @@ impl HTTPAPI {
         &self.config
     }
 
-    pub async fn handle(&self, req: Request<()>) -> Result<Body> {
+    pub async fn handle(&self, mut req: Request<()>) -> Result<Response> {
+        if let Some(res) = self.guard.check(&mut req).await? {
+            return Ok(res);
+        }
+        self.dispatch(req).await.map(Response::from)
+    }
+
+    async fn dispatch(&self, req: Request<()>) -> Result<Body> {
+        match &self.w {
+            WType::SqlRunner(w) => match w.upgrade() {
+                Some(a) => {
+                    return a.handle(req).await;
+                }
+                _ => {}
+            },
+            WType::SagaAggregator(w) => match w.upgrade() {
+                Some(a) => {
+                    return a.handle(req).await;
+                }
+                _ => {}
+            },
+            WType::SqlTransaction(w) => match w.upgrade() {
+                Some(a) => {
+                    return a.handle(req).await;
+                }
+                _ => {}
+            },
+            WType::Cache(w) => match w.upgrade() {
+                Some(a) => {
+                    return a.handle(req).await;
+                }
+                _ => {}
+            },
+            WType::CacheInvalidator(w) => match w.upgrade() {
+                Some(a) => {
+                    return a.handle(req).await;
+                }
+                _ => {}
+            },
+            WType::SqlQuery(w) => match w.upgrade() {
+                Some(a) => {
+                    return a.handle(req).await;
+                }
+                _ => {}
+            },
+            WType::SqlImport(w) => match w.upgrade() {
+                Some(a) => {
+                    return a.handle(req).await;
+                }
+                _ => {}
+            },
+            WType::None => {}
+        }
+
+        Ok(Body::from_string("empty".to_string()))
+    }
+}
+
+/// The auth and rbac operators applied to an api. `SqlCrud` has one too, since its routes
+/// don't go through `HTTPAPI`.
+#[derive(Default)]
+pub struct Guard {
+    auth: AuthType,
+    rbac: Option<Weak<Rbac>>,
+}
+
+impl Guard {
+    /// Authenticates and authorizes `req`, setting its `Principal`. A rejected request gets
+    /// the returned response, such as a 401 with a challenge.
+    pub async fn check(&self, req: &mut Request<()>) -> Result<Option<Response>> {
         match &self.auth {
             AuthType::SimpleAuth(auth) => match auth.upgrade() {
                 Some(a) => {
@@ -67,36 +162,66 @@ impl HTTPAPI {
                         None => {}
                     };
                     if !ok {
-                        return Ok(Body::from_string("permission denied".to_string()));
+                        return Ok(Some(
+                            Body::from_string("permission denied".to_string()).into(),
+                        ));
                     }
                 }
                 _ => {}
             },
-            AuthType::None => {}
-        }
-        match &self.w {
-            WType::SqlRunner(w) => match w.upgrade() {
-                Some(a) => match a.run_sql().await {
-                    Some(json) => {
-                        return Body::from_json(&json);
-                    }
-                    _ => {}
+            // the claims are exposed to downstream operators as the request's `Principal`
+            AuthType::Jwt(auth) => match auth.upgrade() {
+                Some(a) => match a.auth(req) {
+                    Ok(principal) => req.set_ext(principal),
+                    Err(err) => return Ok(Some(challenge("Bearer".to_string(), err))),
                 },
-                _ => {}
+                _ => return Err(auth_down()),
             },
-            WType::SagaAggregator(w) => match w.upgrade() {
+            AuthType::ApiKey(auth) => match auth.upgrade() {
                 Some(a) => {
-                    return a.handle(req).await;
+                    let principal = a
+                        .auth(req)
+                        .await
+                        .map_err(|e| tide::Error::new(e.status(), e))?;
+                    req.set_ext(principal);
                 }
-                _ => {}
+                _ => return Err(auth_down()),
             },
-            WType::None => {}
+            AuthType::Basic(auth) => match auth.upgrade() {
+                Some(a) => match a.auth(req).await {
+                    Ok(principal) => req.set_ext(principal),
+                    Err(err) if err.status() == StatusCode::Unauthorized => {
+                        return Ok(Some(challenge(a.challenge(), err)))
+                    }
+                    Err(err) => return Err(tide::Error::new(err.status(), err)),
+                },
+                _ => return Err(auth_down()),
+            },
+            AuthType::None => {}
         }
-
-        Ok(Body::from_string("empty".to_string()))
+        // after the auth, which sets the principal the roles come from
+        if let Some(rbac) = &self.rbac {
+            let a = rbac.upgrade().ok_or_else(auth_down)?;
+            a.authorize(req)
+                .await
+                .map_err(|e| tide::Error::new(e.status(), e))?;
+        }
+        Ok(None)
     }
 }
 
+// a 401 telling the client how to authenticate
+fn challenge(value: String, err: impl std::fmt::Display) -> Response {
+    let mut res = Response::new(StatusCode::Unauthorized);
+    res.insert_header(CHALLENGEKEY, value);
+    res.set_body(err.to_string());
+    res
+}
+
+fn auth_down() -> tide::Error {
+    tide::Error::from_str(StatusCode::InternalServerError, "auth is down")
+}
+
 impl super::Operator for HTTPAPI {}
 
 impl super::Monad<SqlRunner> for HTTPAPI {
@@ -115,10 +240,122 @@ impl super::Monad<SagaAggregator> for HTTPAPI {
     }
 }
 
+impl super::Monad<SqlTransaction> for HTTPAPI {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<SqlTransaction>) -> Self::Result {
+        self.w = WType::SqlTransaction(w);
+    }
+}
+
+impl super::Monad<Cache> for HTTPAPI {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<Cache>) -> Self::Result {
+        self.w = WType::Cache(w);
+    }
+}
+
+impl super::Monad<CacheInvalidator> for HTTPAPI {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<CacheInvalidator>) -> Self::Result {
+        self.w = WType::CacheInvalidator(w);
+    }
+}
+
+impl super::Monad<SqlQuery> for HTTPAPI {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<SqlQuery>) -> Self::Result {
+        self.w = WType::SqlQuery(w);
+    }
+}
+
+impl super::Monad<SqlImport> for HTTPAPI {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<SqlImport>) -> Self::Result {
+        self.w = WType::SqlImport(w);
+    }
+}
+
 impl super::Monad<SimpleAuth> for HTTPAPI {
     type Result = ();
 
+    fn apply(&mut self, w: Weak<SimpleAuth>) -> Self::Result {
+        self.guard.apply(w);
+    }
+}
+
+impl super::Monad<SimpleAuth> for Guard {
+    type Result = ();
+
     fn apply(&mut self, w: Weak<SimpleAuth>) -> Self::Result {
         self.auth = AuthType::SimpleAuth(w);
     }
 }
+
+impl super::Monad<JwtAuth> for HTTPAPI {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<JwtAuth>) -> Self::Result {
+        self.guard.apply(w);
+    }
+}
+
+impl super::Monad<JwtAuth> for Guard {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<JwtAuth>) -> Self::Result {
+        self.auth = AuthType::Jwt(w);
+    }
+}
+
+impl super::Monad<ApiKey> for HTTPAPI {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<ApiKey>) -> Self::Result {
+        self.guard.apply(w);
+    }
+}
+
+impl super::Monad<ApiKey> for Guard {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<ApiKey>) -> Self::Result {
+        self.auth = AuthType::ApiKey(w);
+    }
+}
+
+impl super::Monad<BasicAuth> for HTTPAPI {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<BasicAuth>) -> Self::Result {
+        self.guard.apply(w);
+    }
+}
+
+impl super::Monad<BasicAuth> for Guard {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<BasicAuth>) -> Self::Result {
+        self.auth = AuthType::Basic(w);
+    }
+}
+
+impl super::Monad<Rbac> for HTTPAPI {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<Rbac>) -> Self::Result {
+        self.guard.apply(w);
+    }
+}
+
+impl super::Monad<Rbac> for Guard {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<Rbac>) -> Self::Result {
+        self.rbac = Some(w);
+    }
+}
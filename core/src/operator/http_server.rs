@@ -1,15 +1,18 @@
 use super::http_api::{Method, HTTPAPI};
+use super::sql_crud::SqlCrud;
 use super::OperatorError;
 use async_std::sync::Weak;
 use async_trait::async_trait;
 use futures::FutureExt;
 use serde::{Deserialize, Serialize};
+use std::future::Future;
 
-use tide::Body;
+use tide::{Body, Endpoint, Response, Route, StatusCode};
 
 pub struct HTTPServer {
     config: Config,
     ws: Vec<Weak<HTTPAPI>>,
+    cruds: Vec<Weak<SqlCrud>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -22,6 +25,7 @@ impl HTTPServer {
         Self {
             config,
             ws: Vec::default(),
+            cruds: Vec::default(),
         }
     }
 }
@@ -42,46 +46,38 @@ impl super::Source for HTTPServer {
                         let w = w.clone();
                         return async move {
                             match w.upgrade() {
-                                Some(a) => {
-                                    let body = std::panic::AssertUnwindSafe(a.handle(req))
-                                        .catch_unwind()
-                                        .await;
-
-                                    match body {
-                                        Ok(result) => match result {
-                                            Ok(body) => Ok(body),
-                                            Err(err) => Ok(Body::from_string(format!(
-                                                "handler error: {:?}",
-                                                err
-                                            ))),
-                                        },
-                                        Err(err) => Ok(Body::from_string(format!(
-                                            "panic in handler: {:?}",
-                                            err
-                                        ))),
-                                    }
-                                }
-                                _ => Ok(Body::from_string("handler is down".to_string())),
+                                Some(a) => respond(a.handle(req)).await,
+                                _ => Ok(Body::from_string("handler is down".to_string()).into()),
                             }
                         };
                     };
 
-                    match config.method {
-                        Method::GET => {
-                            route.get(handler);
-                        }
-                        Method::POST => {
-                            route.post(handler);
-                        }
-                        Method::DELETE => {
-                            route.delete(handler);
-                        }
-                        Method::PUT => {
-                            route.put(handler);
-                        }
-                        Method::ALL => {
-                            route.all(handler);
-                        }
+                    route_method(&mut route, &config.method, handler);
+                }
+                None => {}
+            }
+        }
+
+        for w in &self.cruds {
+            match w.upgrade() {
+                Some(a) => {
+                    for (uri, method, action) in a.routes() {
+                        let mut route = app.at(uri.as_str());
+
+                        let w = w.clone();
+                        let handler = move |req| {
+                            let w = w.clone();
+                            return async move {
+                                match w.upgrade() {
+                                    Some(a) => respond(a.handle(action, req)).await,
+                                    _ => {
+                                        Ok(Body::from_string("handler is down".to_string()).into())
+                                    }
+                                }
+                            };
+                        };
+
+                        route_method(&mut route, &method, handler);
                     }
                 }
                 None => {}
@@ -94,6 +90,54 @@ impl super::Source for HTTPServer {
     }
 }
 
+// handler errors keep their status, e.g. 400 for a bad param or 404 for no rows
+async fn respond<F, R>(handle: F) -> tide::Result<Response>
+where
+    F: Future<Output = tide::Result<R>>,
+    R: Into<Response>,
+{
+    let body = std::panic::AssertUnwindSafe(handle).catch_unwind().await;
+
+    match body {
+        Ok(result) => match result {
+            Ok(body) => Ok(body.into()),
+            Err(err) => {
+                let mut res = Response::new(err.status());
+                res.set_body(format!("handler error: {:?}", err));
+                Ok(res)
+            }
+        },
+        Err(err) => {
+            let mut res = Response::new(StatusCode::InternalServerError);
+            res.set_body(format!("panic in handler: {:?}", err));
+            Ok(res)
+        }
+    }
+}
+
+fn route_method<E: Endpoint<()>>(route: &mut Route<()>, method: &Method, handler: E) {
+    match method {
+        Method::GET => {
+            route.get(handler);
+        }
+        Method::POST => {
+            route.post(handler);
+        }
+        Method::DELETE => {
+            route.delete(handler);
+        }
+        Method::PUT => {
+            route.put(handler);
+        }
+        Method::PATCH => {
+            route.patch(handler);
+        }
+        Method::ALL => {
+            route.all(handler);
+        }
+    }
+}
+
 impl super::Monad<HTTPAPI> for HTTPServer {
     type Result = ();
 
@@ -101,3 +145,11 @@ impl super::Monad<HTTPAPI> for HTTPServer {
         self.ws.push(w);
     }
 }
+
+impl super::Monad<SqlCrud> for HTTPServer {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<SqlCrud>) -> Self::Result {
+        self.cruds.push(w);
+    }
+}
@@ -0,0 +1,384 @@
+use super::Principal;
+use jsonwebtoken::errors::Error as JwtLibError;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::HashSet;
+use thiserror::Error;
+use tide::{Request, StatusCode};
+
+/// Verifies `Authorization: Bearer` tokens and turns their claims into a `Principal`.
+pub struct JwtAuth {
+    config: Config,
+    keys: Vec<Key>,
+}
+
+struct Key {
+    kid: Option<String>,
+    alg: Algorithm,
+    key: DecodingKey<'static>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct Config {
+    #[serde(default)]
+    pub keys: Vec<KeyConfig>,
+    /// a JWKS document with RSA and P-256 keys, read once at startup
+    #[serde(default)]
+    pub jwks_file: Option<String>,
+    /// seconds of clock skew allowed on `exp` and `nbf`
+    #[serde(default)]
+    pub leeway: u64,
+    /// required `iss`, when set
+    #[serde(default)]
+    pub issuer: Option<String>,
+    /// `aud` must hold one of these, when any
+    #[serde(default)]
+    pub audience: Vec<String>,
+    /// claim of the principal name, `sub` by default
+    #[serde(default)]
+    pub name_claim: Option<String>,
+    /// claim of the principal roles, an array or a space separated string; `roles` by default
+    #[serde(default)]
+    pub roles_claim: Option<String>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct KeyConfig {
+    /// matched against the token's `kid`, a key without one is tried for any token
+    #[serde(default)]
+    pub kid: Option<String>,
+    pub alg: Alg,
+    /// the HS256 secret or the PEM public key
+    #[serde(default)]
+    pub key: Option<String>,
+    /// read instead of `key`
+    #[serde(default)]
+    pub file: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum Alg {
+    HS256,
+    RS256,
+    ES256,
+}
+
+impl Default for Alg {
+    fn default() -> Self {
+        Alg::HS256
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum JwtError {
+    #[error("bearer token is missing")]
+    Missing,
+    #[error("no key for the token")]
+    NoKey,
+    #[error("invalid token: {0}")]
+    Invalid(#[from] JwtLibError),
+}
+
+impl JwtError {
+    pub fn status(&self) -> StatusCode {
+        StatusCode::Unauthorized
+    }
+}
+
+const AUTHKEY: &str = "Authorization";
+
+impl super::Operator for JwtAuth {}
+
+impl JwtAuth {
+    pub fn new(config: Config) -> Self {
+        let mut keys = vec![];
+        for k in &config.keys {
+            let pem = match (&k.key, &k.file) {
+                (Some(key), None) => key.clone().into_bytes(),
+                (None, Some(file)) => match std::fs::read(file) {
+                    Ok(bytes) => bytes,
+                    Err(err) => panic!("invalid jwt_auth config: {}: {}", file, err),
+                },
+                _ => panic!("invalid jwt_auth config: a key needs one of key and file"),
+            };
+            let key = match k.alg {
+                Alg::HS256 => Ok(DecodingKey::from_secret(&pem).into_static()),
+                Alg::RS256 => DecodingKey::from_rsa_pem(&pem).map(DecodingKey::into_static),
+                Alg::ES256 => DecodingKey::from_ec_pem(&pem).map(DecodingKey::into_static),
+            };
+            match key {
+                Ok(key) => keys.push(Key {
+                    kid: k.kid.clone(),
+                    alg: k.alg.into(),
+                    key,
+                }),
+                Err(err) => panic!("invalid jwt_auth config: {}", err),
+            }
+        }
+        if let Some(file) = &config.jwks_file {
+            let jwks = std::fs::read(file)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| jwks(&bytes));
+            match jwks {
+                Ok(jwks) => keys.extend(jwks),
+                Err(err) => panic!("invalid jwt_auth config: {}: {}", file, err),
+            }
+        }
+        if keys.is_empty() {
+            panic!("invalid jwt_auth config: no keys");
+        }
+
+        JwtAuth { config, keys }
+    }
+
+    /// The principal of the bearer token of `req`.
+    pub fn auth(&self, req: &Request<()>) -> Result<Principal, JwtError> {
+        let header = req.header(AUTHKEY).ok_or(JwtError::Missing)?;
+        let token = header
+            .as_str()
+            .strip_prefix("Bearer ")
+            .ok_or(JwtError::Missing)?;
+        self.verify(token.trim())
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Principal, JwtError> {
+        let header = decode_header(token)?;
+        // the algorithm comes from the key, so a token can't pick a weaker one
+        let candidates = self
+            .keys
+            .iter()
+            .filter(|k| k.alg == header.alg && (k.kid.is_none() || k.kid == header.kid));
+
+        let mut last = JwtError::NoKey;
+        for k in candidates {
+            match decode::<serde_json::Map<String, JsonValue>>(
+                token,
+                &k.key,
+                &self.validation(k.alg),
+            ) {
+                Ok(data) => return Ok(self.principal(data.claims)),
+                Err(err) => last = JwtError::Invalid(err),
+            }
+        }
+        Err(last)
+    }
+
+    fn validation(&self, alg: Algorithm) -> Validation {
+        let mut validation = Validation::new(alg);
+        validation.leeway = self.config.leeway;
+        validation.validate_nbf = true;
+        validation.iss = self.config.issuer.clone();
+        if !self.config.audience.is_empty() {
+            let aud: HashSet<String> = self.config.audience.iter().cloned().collect();
+            validation.aud = Some(aud);
+        }
+        validation
+    }
+
+    fn principal(&self, claims: serde_json::Map<String, JsonValue>) -> Principal {
+        let name_claim = self.config.name_claim.as_deref().unwrap_or("sub");
+        let roles_claim = self.config.roles_claim.as_deref().unwrap_or("roles");
+        let name = match claims.get(name_claim) {
+            Some(JsonValue::String(s)) => s.clone(),
+            Some(JsonValue::Number(n)) => n.to_string(),
+            _ => String::new(),
+        };
+        let roles = match claims.get(roles_claim) {
+            Some(JsonValue::Array(roles)) => roles
+                .iter()
+                .filter_map(|r| r.as_str().map(str::to_string))
+                .collect(),
+            Some(JsonValue::String(s)) => s.split_whitespace().map(str::to_string).collect(),
+            _ => vec![],
+        };
+        Principal {
+            name,
+            roles,
+            claims,
+        }
+    }
+}
+
+impl From<Alg> for Algorithm {
+    fn from(alg: Alg) -> Self {
+        match alg {
+            Alg::HS256 => Algorithm::HS256,
+            Alg::RS256 => Algorithm::RS256,
+            Alg::ES256 => Algorithm::ES256,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+    #[serde(default)]
+    x: Option<String>,
+    #[serde(default)]
+    y: Option<String>,
+}
+
+// keys of other types or curves are skipped
+fn jwks(bytes: &[u8]) -> Result<Vec<Key>, String> {
+    let jwks: Jwks = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+    let mut keys = vec![];
+    for jwk in jwks.keys {
+        match (jwk.kty.as_str(), jwk.crv.as_deref()) {
+            ("RSA", _) => {
+                let (n, e) = match (&jwk.n, &jwk.e) {
+                    (Some(n), Some(e)) => (n, e),
+                    _ => return Err("RSA key without n or e".to_string()),
+                };
+                keys.push(Key {
+                    kid: jwk.kid.clone(),
+                    alg: Algorithm::RS256,
+                    key: DecodingKey::from_rsa_components(n, e).into_static(),
+                });
+            }
+            ("EC", Some("P-256")) => {
+                let coordinate = |c: &Option<String>| {
+                    c.as_deref()
+                        .ok_or_else(|| "EC key without x or y".to_string())
+                        .and_then(|c| {
+                            base64::decode_config(c, base64::URL_SAFE_NO_PAD)
+                                .map_err(|e| e.to_string())
+                        })
+                };
+                // the uncompressed point
+                let mut point = vec![4u8];
+                point.extend(coordinate(&jwk.x)?);
+                point.extend(coordinate(&jwk.y)?);
+                keys.push(Key {
+                    kid: jwk.kid.clone(),
+                    alg: Algorithm::ES256,
+                    key: DecodingKey::from_ec_der(&point).into_static(),
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    fn now() -> i64 {
+        chrono::Utc::now().timestamp()
+    }
+
+    fn token(claims: JsonValue) -> String {
+        encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap()
+    }
+
+    fn auth() -> JwtAuth {
+        JwtAuth::new(Config {
+            keys: vec![KeyConfig {
+                alg: Alg::HS256,
+                key: Some("secret".to_string()),
+                ..Default::default()
+            }],
+            leeway: 30,
+            issuer: Some("https://issuer".to_string()),
+            audience: vec!["api".to_string()],
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn verify() {
+        let auth = auth();
+        let claims = json!({
+            "sub": "alice",
+            "roles": ["admin", "read"],
+            "tenant": "acme",
+            "iss": "https://issuer",
+            "aud": "api",
+            "exp": now() - 10,
+        });
+        // expired, but within the leeway
+        let principal = auth.verify(&token(claims.clone())).unwrap();
+        assert_eq!(principal.name, "alice");
+        assert_eq!(principal.roles, vec!["admin", "read"]);
+        assert_eq!(principal.claims["tenant"], json!("acme"));
+
+        let mut expired = claims.clone();
+        expired["exp"] = json!(now() - 60);
+        assert!(auth.verify(&token(expired)).is_err());
+
+        let mut early = claims.clone();
+        early["nbf"] = json!(now() + 60);
+        assert!(auth.verify(&token(early)).is_err());
+
+        let mut other = claims.clone();
+        other["aud"] = json!("other");
+        assert!(auth.verify(&token(other)).is_err());
+
+        let mut other = claims;
+        other["iss"] = json!("https://other");
+        assert!(auth.verify(&token(other)).is_err());
+    }
+
+    #[test]
+    fn key() {
+        let auth = auth();
+        let claims =
+            json!({"sub": "alice", "iss": "https://issuer", "aud": "api", "exp": now() + 60});
+
+        let forged = encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(b"other"),
+        )
+        .unwrap();
+        assert!(matches!(auth.verify(&forged), Err(JwtError::Invalid(_))));
+
+        // no key for the algorithm
+        let hs384 = encode(
+            &Header::new(Algorithm::HS384),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert!(matches!(auth.verify(&hs384), Err(JwtError::NoKey)));
+    }
+
+    #[test]
+    fn jwks_keys() {
+        let keys = jwks(
+            json!({"keys": [
+                {"kty": "RSA", "kid": "r1", "n": "sXchDaQebHnPiGvyDOAT4saGEUetSyo9MKLOoWFsueri23bOdgWp4Dy1WlUzewbgBHod5pcM9H95GQRV3JDXboIRROSBigeC5yjU1hGzHHyXss8UDprecbAYxknTcQkhslANGRUZmdTOQ5qTRsLAt6BTYuyvVRdhS8exSZEy_c4gs_7svlJJQ4H9_NxsiIoLwAEk7-Q3UXERGYw_75IDrGA84-lA_-Ct4eTlXHBIY2EaV7t7LjJaynVJCpkv4LKjTTAumiGUIuQhrNhZLuF_RJLqHpM2kgWFLU7-VTdL1VbC2tejvcI2BlMkEpk1BzBZI0KQB0GaDWFLN-aEAw3vRw", "e": "AQAB"},
+                {"kty": "EC", "crv": "P-256", "kid": "e1", "x": "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU", "y": "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0"},
+                {"kty": "oct", "k": "c2VjcmV0"}
+            ]})
+            .to_string()
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].alg, Algorithm::RS256);
+        assert_eq!(keys[1].kid.as_deref(), Some("e1"));
+    }
+}
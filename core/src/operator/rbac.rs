@@ -0,0 +1,215 @@
+use super::watched::Watched;
+use super::Principal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+use tide::{Request, StatusCode};
+
+/// Allows a request when a role of its `Principal`, set by an auth operator applied to the
+/// same api, has a permission matching the method and the path.
+///
+/// A policy from `file` is read again whenever the file changes, one that can't be read
+/// or parsed keeps the policy read before.
+pub struct Rbac {
+    config: Config,
+    file: Option<Watched<Policy>>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct Config {
+    #[serde(default)]
+    pub policy: Policy,
+    /// a json policy used instead of `policy`
+    #[serde(default)]
+    pub file: Option<String>,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Policy {
+    /// the permissions of each role
+    pub roles: HashMap<String, Vec<Permission>>,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Permission {
+    /// any method when empty, `GET` also allows `HEAD`
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// `/` separated segments, where `*` or `:name` matches one segment and a last `**`
+    /// matches the rest of the path, including nothing
+    pub path: String,
+}
+
+#[derive(Error, Debug)]
+pub enum RbacError {
+    #[error("request is not authenticated")]
+    Unauthenticated,
+    #[error("`{0}` may not {1} {2}")]
+    Denied(String, String, String),
+}
+
+impl RbacError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            RbacError::Unauthenticated => StatusCode::Unauthorized,
+            RbacError::Denied(..) => StatusCode::Forbidden,
+        }
+    }
+}
+
+impl super::Operator for Rbac {}
+
+impl Rbac {
+    pub fn new(config: Config) -> Self {
+        let file = config
+            .file
+            .as_ref()
+            .map(|file| match Watched::new(file, read) {
+                Ok(watched) => watched,
+                Err(err) => panic!("invalid rbac config: {}: {}", file, err),
+            });
+
+        Rbac { config, file }
+    }
+
+    pub async fn authorize(&self, req: &Request<()>) -> Result<(), RbacError> {
+        let principal = req.ext::<Principal>().ok_or(RbacError::Unauthenticated)?;
+        let method = req.method().to_string();
+        self.check(principal, &method, req.url().path()).await
+    }
+
+    pub async fn check(
+        &self,
+        principal: &Principal,
+        method: &str,
+        path: &str,
+    ) -> Result<(), RbacError> {
+        let watched;
+        let policy = match &self.file {
+            Some(file) => {
+                watched = file.get().await;
+                &*watched
+            }
+            None => &self.config.policy,
+        };
+        let allowed = principal
+            .roles
+            .iter()
+            .filter_map(|role| policy.roles.get(role))
+            .flatten()
+            .any(|p| p.allows(method, path));
+        match allowed {
+            true => Ok(()),
+            false => Err(RbacError::Denied(
+                principal.name.clone(),
+                method.to_string(),
+                path.to_string(),
+            )),
+        }
+    }
+}
+
+impl Permission {
+    fn allows(&self, method: &str, path: &str) -> bool {
+        let method_ok = self.methods.is_empty()
+            || self.methods.iter().any(|m| {
+                m == "*"
+                    || m.eq_ignore_ascii_case(method)
+                    || (m.eq_ignore_ascii_case("GET") && method.eq_ignore_ascii_case("HEAD"))
+            });
+        method_ok && matches(&self.path, path)
+    }
+}
+
+fn matches(pattern: &str, path: &str) -> bool {
+    let mut path = segments(path);
+    let mut pattern = segments(pattern).peekable();
+    while let Some(p) = pattern.next() {
+        if p == "**" && pattern.peek().is_none() {
+            return true;
+        }
+        match path.next() {
+            Some(s) if p == "*" || p.starts_with(':') || p == s => {}
+            _ => return false,
+        }
+    }
+    path.next().is_none()
+}
+
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|s| !s.is_empty())
+}
+
+fn read(bytes: &[u8]) -> std::io::Result<Policy> {
+    serde_json::from_slice(bytes)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn principal(roles: &[&str]) -> Principal {
+        Principal {
+            name: "alice".to_string(),
+            roles: roles.iter().map(|r| r.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pattern() {
+        assert!(matches("/admin/**", "/admin"));
+        assert!(matches("/admin/**", "/admin/users/1"));
+        assert!(matches("/users/:id", "/users/1/"));
+        assert!(matches("/users/*/orders", "/users/1/orders"));
+        assert!(!matches("/users/:id", "/users/1/orders"));
+        assert!(!matches("/admin", "/administrator"));
+        assert!(matches("/", "/"));
+    }
+
+    #[async_std::test]
+    async fn check() {
+        let policy: Policy = serde_json::from_value(json!({"roles": {
+            "read": [{"methods": ["GET"], "path": "/**"}],
+            "admin": [{"path": "/admin/**"}]
+        }}))
+        .unwrap();
+        let rbac = Rbac::new(Config {
+            policy,
+            ..Default::default()
+        });
+
+        let reader = principal(&["read"]);
+        assert!(rbac.check(&reader, "GET", "/users").await.is_ok());
+        assert!(rbac.check(&reader, "HEAD", "/users").await.is_ok());
+        assert!(matches!(
+            rbac.check(&reader, "DELETE", "/admin/users").await,
+            Err(RbacError::Denied(..))
+        ));
+        let admin = principal(&["admin"]);
+        assert!(rbac.check(&admin, "DELETE", "/admin/users").await.is_ok());
+        assert!(rbac.check(&admin, "GET", "/").await.is_err());
+        assert!(rbac.check(&principal(&[]), "GET", "/").await.is_err());
+    }
+
+    #[async_std::test]
+    async fn reload() {
+        let path = std::env::temp_dir().join("rbac_policy.json");
+        std::fs::write(&path, r#"{"roles": {"read": [{"path": "/"}]}}"#).unwrap();
+        let rbac = Rbac::new(Config {
+            file: Some(path.to_string_lossy().to_string()),
+            ..Default::default()
+        });
+        let reader = principal(&["read"]);
+        assert!(rbac.check(&reader, "GET", "/").await.is_ok());
+
+        std::fs::write(&path, r#"{"roles": {"read": [{"path": "/users"}]}}"#).unwrap();
+        assert!(rbac.check(&reader, "GET", "/").await.is_err());
+
+        // a broken policy keeps the one before
+        std::fs::write(&path, "{").unwrap();
+        assert!(rbac.check(&reader, "GET", "/users").await.is_ok());
+    }
+}
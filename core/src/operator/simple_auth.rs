@@ -15,8 +15,14 @@ impl SimpleAuth {
     }
 
     pub fn auth(&self, key: &str) -> bool {
-        self.config.secret == key
+        constant_time_eq(self.config.secret.as_bytes(), key.as_bytes())
     }
 }
 
+/// Compares without returning early, so the time taken doesn't tell how much of a
+/// secret matched; only the length is not hidden.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 impl super::Operator for SimpleAuth {}
@@ -0,0 +1,521 @@
+use super::super::Principal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use thiserror::Error;
+use tide::Request;
+
+/// A named bind, referenced as `:name` in the sql.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Param {
+    pub name: String,
+    pub from: Origin,
+    /// key to look up in `from`, defaults to `name`
+    #[serde(default)]
+    pub key: Option<String>,
+    #[serde(rename = "type", default)]
+    pub ty: Type,
+    #[serde(default)]
+    pub default: Option<JsonValue>,
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq)]
+pub enum Origin {
+    Path,
+    Query,
+    Body,
+    Claim,
+    /// output of an earlier statement in the same transaction, keyed as `statement.field`
+    Output,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq)]
+pub enum Type {
+    String,
+    Int,
+    Float,
+    Bool,
+}
+
+impl Default for Type {
+    fn default() -> Self {
+        Type::String
+    }
+}
+
+/// A resolved bind, always passed as a prepared statement argument.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+}
+
+#[derive(Error, Debug)]
+pub enum ParamError {
+    #[error("param `{0}` is required")]
+    Missing(String),
+    #[error("param `{name}` is not a valid {ty}")]
+    Invalid { name: String, ty: &'static str },
+    #[error("placeholder `:{0}` is not declared in params")]
+    Undeclared(String),
+}
+
+/// Everything a param can be bound from, extracted once per request.
+#[derive(Default)]
+pub struct Input {
+    pub path: HashMap<String, String>,
+    pub query: HashMap<String, String>,
+    pub body: Option<JsonValue>,
+    pub principal: Option<Principal>,
+    pub outputs: serde_json::Map<String, JsonValue>,
+    /// set by a runner applied to a `tenant_router`
+    pub tenant: Option<String>,
+}
+
+impl Input {
+    pub async fn from_request(req: &mut Request<()>, params: &[Param]) -> tide::Result<Input> {
+        let mut input = Input::default();
+
+        for p in params.iter().filter(|p| p.from == Origin::Path) {
+            let key = p.key();
+            if let Ok(v) = req.param::<String>(key) {
+                input.path.insert(key.to_string(), v);
+            }
+        }
+
+        input.query = req
+            .url()
+            .query_pairs()
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+
+        if params.iter().any(|p| p.from == Origin::Body) {
+            let body = req.body_string().await?;
+            if !body.trim().is_empty() {
+                input.body = Some(serde_json::from_str(body.as_str())?);
+            }
+        }
+
+        input.principal = req.ext::<Principal>().cloned();
+
+        Ok(input)
+    }
+
+    fn lookup(&self, param: &Param) -> Option<Lookup> {
+        let key = param.key();
+        match param.from {
+            Origin::Path => self.path.get(key).map(|v| Lookup::Str(v.as_str())),
+            Origin::Query => self.query.get(key).map(|v| Lookup::Str(v.as_str())),
+            Origin::Body => self
+                .body
+                .as_ref()
+                .and_then(|b| b.get(key))
+                .map(Lookup::Json),
+            Origin::Claim => self
+                .principal
+                .as_ref()
+                .and_then(|p| p.claims.get(key))
+                .map(Lookup::Json),
+            Origin::Output => {
+                let mut parts = key.splitn(2, '.');
+                let output = self.outputs.get(parts.next()?)?;
+                // rows of a fetch resolve against the first row
+                let output = match output {
+                    JsonValue::Array(rows) => rows.first()?,
+                    _ => output,
+                };
+                match parts.next() {
+                    Some(field) => output.get(field),
+                    None => Some(output),
+                }
+                .map(Lookup::Json)
+            }
+        }
+    }
+}
+
+enum Lookup<'a> {
+    Str(&'a str),
+    Json(&'a JsonValue),
+}
+
+impl Param {
+    fn key(&self) -> &str {
+        self.key.as_ref().unwrap_or(&self.name).as_str()
+    }
+
+    pub fn resolve(&self, input: &Input) -> Result<Value, ParamError> {
+        let found = match input.lookup(self) {
+            Some(Lookup::Json(JsonValue::Null)) | None => None,
+            found => found,
+        };
+
+        match found {
+            Some(Lookup::Str(s)) => self.ty.parse_str(s),
+            Some(Lookup::Json(v)) => self.ty.parse_json(v),
+            None if self.required => Err(ParamError::Missing(self.name.clone())),
+            None => match &self.default {
+                Some(v) => self.ty.parse_json(v),
+                None => Ok(Some(Value::Null)),
+            },
+        }
+        .and_then(|v| {
+            v.ok_or(ParamError::Invalid {
+                name: self.name.clone(),
+                ty: self.ty.name(),
+            })
+        })
+    }
+}
+
+impl Type {
+    fn name(&self) -> &'static str {
+        match self {
+            Type::String => "string",
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Bool => "bool",
+        }
+    }
+
+    fn parse_str(&self, s: &str) -> Result<Option<Value>, ParamError> {
+        Ok(match self {
+            Type::String => Some(Value::String(s.to_string())),
+            Type::Int => s.trim().parse().ok().map(Value::Int),
+            Type::Float => s.trim().parse().ok().map(Value::Float),
+            Type::Bool => match s.trim() {
+                "true" | "1" => Some(Value::Bool(true)),
+                "false" | "0" => Some(Value::Bool(false)),
+                _ => None,
+            },
+        })
+    }
+
+    fn parse_json(&self, v: &JsonValue) -> Result<Option<Value>, ParamError> {
+        match v {
+            JsonValue::Null => Ok(Some(Value::Null)),
+            JsonValue::String(s) => self.parse_str(s.as_str()),
+            _ => Ok(match self {
+                Type::String => Some(Value::String(v.to_string())),
+                Type::Int => v.as_i64().map(Value::Int),
+                Type::Float => v.as_f64().map(Value::Float),
+                Type::Bool => v.as_bool().map(Value::Bool),
+            }),
+        }
+    }
+}
+
+/// Sql with `:name` placeholders rewritten to positional `?`,
+/// and to `$1, $2, ...` in `numbered` for postgres.
+/// The two are lexed by their own dialect, so they may find different placeholders.
+pub struct Statement {
+    pub sql: String,
+    pub numbered: String,
+    names: Vec<String>,
+    numbered_names: Vec<String>,
+}
+
+impl Statement {
+    pub fn new(sql: &str, params: &[Param]) -> Result<Statement, ParamError> {
+        let (numbered, numbered_names) = rewrite(sql, true);
+        let (sql, names) = rewrite(sql, false);
+        // a name seen by only one dialect fails when that dialect resolves it
+        for name in names.iter().filter(|n| numbered_names.contains(n)) {
+            if !params.iter().any(|p| &p.name == name) {
+                return Err(ParamError::Undeclared(name.clone()));
+            }
+        }
+        Ok(Statement {
+            sql,
+            numbered,
+            names,
+            numbered_names,
+        })
+    }
+
+    /// Resolves the args of `sql` in placeholder order; a name used twice is bound twice.
+    /// Only the params used by this statement are resolved.
+    pub fn args(&self, params: &[Param], input: &Input) -> Result<Vec<Value>, ParamError> {
+        resolve(&self.names, params, input)
+    }
+
+    /// Same as `args`, for `numbered`.
+    pub fn numbered_args(&self, params: &[Param], input: &Input) -> Result<Vec<Value>, ParamError> {
+        resolve(&self.numbered_names, params, input)
+    }
+}
+
+fn resolve(names: &[String], params: &[Param], input: &Input) -> Result<Vec<Value>, ParamError> {
+    let mut resolved = HashMap::new();
+    let mut args = vec![];
+    for name in names {
+        if !resolved.contains_key(name.as_str()) {
+            let p = params
+                .iter()
+                .find(|p| &p.name == name)
+                .ok_or_else(|| ParamError::Undeclared(name.clone()))?;
+            resolved.insert(name.as_str(), p.resolve(input)?);
+        }
+        args.push(resolved[&name.as_str()].clone());
+    }
+
+    Ok(args)
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// `$$`, `$tag$`, but not the `$1` of a positional param
+fn dollar_tag(chars: &[char]) -> Option<usize> {
+    if chars.get(1).map_or(false, |c| c.is_ascii_digit()) {
+        return None;
+    }
+    let mut j = 1;
+    while j < chars.len() && is_ident(chars[j]) {
+        j += 1;
+    }
+    match chars.get(j) {
+        Some('$') => Some(j + 1),
+        _ => None,
+    }
+}
+
+// skips quoted strings, identifiers and comments so that only real placeholders are rewritten,
+// `numbered` lexes them as postgres does, otherwise as mysql does
+fn rewrite(sql: &str, numbered: bool) -> (String, Vec<String>) {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut out = String::with_capacity(sql.len());
+    let mut names = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\'' | '"' | '`' if !(numbered && c == '`') => {
+                // postgres only escapes with a backslash in E'' strings
+                let escapes = if numbered {
+                    c == '\''
+                        && i > 0
+                        && (chars[i - 1] == 'E' || chars[i - 1] == 'e')
+                        && (i < 2 || !is_ident(chars[i - 2]))
+                } else {
+                    c != '`'
+                };
+                out.push(c);
+                i += 1;
+                while i < chars.len() {
+                    out.push(chars[i]);
+                    if chars[i] == '\\' && escapes && i + 1 < chars.len() {
+                        i += 1;
+                        out.push(chars[i]);
+                    } else if chars[i] == c {
+                        break;
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            '-' if chars.get(i + 1) == Some(&'-') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    out.push(chars[i]);
+                    i += 1;
+                }
+            }
+            '$' if numbered
+                && (i == 0 || !is_ident(chars[i - 1]))
+                && dollar_tag(&chars[i..]).is_some() =>
+            {
+                let len = dollar_tag(&chars[i..]).unwrap();
+                let tag = &chars[i..i + len];
+                let mut end = i + len;
+                while end + len <= chars.len() && chars[end..end + len] != *tag {
+                    end += 1;
+                }
+                let end = (end + len).min(chars.len());
+                out.extend(&chars[i..end]);
+                i = end;
+            }
+            '#' if !numbered => {
+                while i < chars.len() && chars[i] != '\n' {
+                    out.push(chars[i]);
+                    i += 1;
+                }
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                out.push_str("/*");
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    out.push(chars[i]);
+                    i += 1;
+                }
+                if i < chars.len() {
+                    out.push_str("*/");
+                }
+                i += 2;
+            }
+            ':' if chars.get(i + 1) == Some(&':') => {
+                out.push_str("::");
+                i += 2;
+            }
+            ':' if chars
+                .get(i + 1)
+                .map_or(false, |c| c.is_ascii_alphabetic() || *c == '_')
+                && (i == 0 || !is_ident(chars[i - 1])) =>
+            {
+                let start = i + 1;
+                i = start;
+                while i < chars.len() && is_ident(chars[i]) {
+                    i += 1;
+                }
+                names.push(chars[start..i].iter().collect());
+                if numbered {
+                    out.push_str(format!("${}", names.len()).as_str());
+                } else {
+                    out.push('?');
+                }
+            }
+            _ => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    (out, names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(name: &str, from: Origin, ty: Type) -> Param {
+        Param {
+            name: name.to_string(),
+            from,
+            key: None,
+            ty,
+            default: None,
+            required: false,
+        }
+    }
+
+    #[test]
+    fn rewrite_placeholders() {
+        let (sql, names) = rewrite(
+            "select * from user where id = :id and name = ':skip' and `a:b` = 1 -- :c\n and age > :age and id < :id",
+            false,
+        );
+        assert_eq!(
+            sql,
+            "select * from user where id = ? and name = ':skip' and `a:b` = 1 -- :c\n and age > ? and id < ?"
+        );
+        assert_eq!(names, vec!["id", "age", "id"]);
+
+        let (sql, names) = rewrite("select '10:30', cast(x as char) /* :y */ from t", false);
+        assert_eq!(sql, "select '10:30', cast(x as char) /* :y */ from t");
+        assert!(names.is_empty());
+
+        let (sql, names) = rewrite("select :a::uuid, :b, :a", true);
+        assert_eq!(sql, "select $1::uuid, $2, $3");
+        assert_eq!(names, vec!["a", "b", "a"]);
+    }
+
+    #[test]
+    fn rewrite_postgres_quotes() {
+        let (sql, names) = rewrite("select 'a\\', :a, E'b\\' :c', :b", true);
+        assert_eq!(sql, "select 'a\\', $1, E'b\\' :c', $2");
+        assert_eq!(names, vec!["a", "b"]);
+
+        let (sql, names) = rewrite(
+            "create function f() returns int as $$ select :x $$; select $body$ :y $body$, :z, $1",
+            true,
+        );
+        assert_eq!(
+            sql,
+            "create function f() returns int as $$ select :x $$; select $body$ :y $body$, $1, $1"
+        );
+        assert_eq!(names, vec!["z"]);
+
+        // mysql reads the same backslash as an escape
+        let (_, names) = rewrite("select 'a\\' :a ', :b", false);
+        assert_eq!(names, vec!["b"]);
+    }
+
+    #[test]
+    fn resolve_args() {
+        let mut limit = param("limit", Origin::Query, Type::Int);
+        limit.default = Some(JsonValue::from(10));
+        let mut id = param("id", Origin::Path, Type::Int);
+        id.required = true;
+        let params = vec![id, limit, param("name", Origin::Body, Type::String)];
+
+        let statement = Statement::new(
+            "select * from user where id = :id and name = :name limit :limit",
+            &params,
+        )
+        .unwrap();
+        assert_eq!(
+            statement.sql,
+            "select * from user where id = ? and name = ? limit ?"
+        );
+
+        let mut input = Input::default();
+        assert!(matches!(
+            statement.args(&params, &input),
+            Err(ParamError::Missing(_))
+        ));
+
+        input.path.insert("id".to_string(), "abc".to_string());
+        assert!(matches!(
+            statement.args(&params, &input),
+            Err(ParamError::Invalid { .. })
+        ));
+
+        input.path.insert("id".to_string(), "7".to_string());
+        input.body = Some(serde_json::json!({"name": "x' or 1=1"}));
+        assert_eq!(
+            statement.args(&params, &input).unwrap(),
+            vec![
+                Value::Int(7),
+                Value::String("x' or 1=1".to_string()),
+                Value::Int(10)
+            ]
+        );
+
+        assert!(matches!(
+            Statement::new("select :missing", &params),
+            Err(ParamError::Undeclared(_))
+        ));
+    }
+
+    #[test]
+    fn resolve_outputs() {
+        let mut order_id = param("order_id", Origin::Output, Type::Int);
+        order_id.key = Some("order.last_insert_id".to_string());
+        let mut price = param("price", Origin::Output, Type::Float);
+        price.key = Some("product.price".to_string());
+        let params = vec![order_id, price];
+
+        let statement =
+            Statement::new("insert into item values (:order_id, :price)", &params).unwrap();
+
+        let mut input = Input::default();
+        input.outputs.insert(
+            "order".to_string(),
+            serde_json::json!({"rows_affected": 1, "last_insert_id": 42}),
+        );
+        input
+            .outputs
+            .insert("product".to_string(), serde_json::json!([{"price": 9.5}]));
+        assert_eq!(
+            statement.args(&params, &input).unwrap(),
+            vec![Value::Int(42), Value::Float(9.5)]
+        );
+    }
+}
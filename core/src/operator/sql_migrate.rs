@@ -0,0 +1,310 @@
+use super::sql::Sql;
+use super::sql_runner::{Backend, Format, SqlRunner};
+use super::OperatorError;
+use async_std::stream::StreamExt;
+use async_std::sync::{Arc, Weak};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::mysql::MySql;
+use sqlx::postgres::Postgres;
+use sqlx::sqlite::Sqlite;
+use sqlx::{Connection, Executor};
+use std::collections::HashMap;
+use std::path::Path;
+use thiserror::Error;
+
+/// Applies the versioned `.sql` files of a directory at startup, in version order.
+///
+/// Files are named `<version>_<name>.sql`, e.g. `0001_create_user.sql`. Each file runs in
+/// its own transaction and is recorded with its checksum, so an applied file that was
+/// edited or removed stops the graph. MySQL commits DDL implicitly, so keep one DDL
+/// statement per file there.
+pub struct SqlMigrate {
+    w: WType,
+    config: Config,
+}
+
+enum WType {
+    Mysql(Weak<Sql<MySql>>),
+    Sqlite(Weak<Sql<Sqlite>>),
+    Postgres(Weak<Sql<Postgres>>),
+    None,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct Config {
+    pub dir: String,
+    /// where applied versions are recorded, `sql_migrations` by default
+    #[serde(default)]
+    pub table: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Migration {
+    pub version: i64,
+    pub name: String,
+    pub sql: String,
+    pub checksum: String,
+}
+
+#[derive(Error, Debug)]
+pub enum SqlMigrateError {
+    #[error("sql is not applied")]
+    NotApplied,
+    #[error("invalid migration file name: {0}")]
+    InvalidName(String),
+    #[error("duplicate migration version: {0}")]
+    Duplicate(i64),
+    #[error("checksum of applied migration {0} changed")]
+    ChecksumMismatch(i64),
+    #[error("applied migration {0} is missing")]
+    Missing(i64),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Sql(#[from] sqlx::Error),
+}
+
+const DEFAULT_TABLE: &str = "sql_migrations";
+
+impl super::Operator for SqlMigrate {}
+
+impl SqlMigrate {
+    pub fn new(config: Config) -> Self {
+        if let Some(table) = &config.table {
+            if !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                panic!("invalid sql_migrate table: {}", table);
+            }
+        }
+
+        SqlMigrate {
+            w: WType::None,
+            config,
+        }
+    }
+
+    fn table(&self) -> &str {
+        self.config.table.as_deref().unwrap_or(DEFAULT_TABLE)
+    }
+
+    /// Applies the pending migrations and returns their versions.
+    pub async fn migrate(&self) -> Result<Vec<i64>, SqlMigrateError> {
+        let migrations = load(Path::new(self.config.dir.as_str()))?;
+        match &self.w {
+            WType::Mysql(w) => self.run(upgrade(w)?, migrations).await,
+            WType::Sqlite(w) => self.run(upgrade(w)?, migrations).await,
+            WType::Postgres(w) => self.run(upgrade(w)?, migrations).await,
+            WType::None => Err(SqlMigrateError::NotApplied),
+        }
+    }
+
+    async fn run<DB: Backend>(
+        &self,
+        a: Arc<Sql<DB>>,
+        migrations: Vec<Migration>,
+    ) -> Result<Vec<i64>, SqlMigrateError>
+    where
+        for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    {
+        let mut con = a.get_executor().await?;
+        (&mut *con)
+            .execute(
+                format!(
+                    "create table if not exists {} (
+                    version bigint primary key,
+                    name varchar(255) not null,
+                    checksum varchar(64) not null,
+                    applied_at timestamp default current_timestamp
+                )",
+                    self.table()
+                )
+                .as_str(),
+            )
+            .await?;
+
+        let mut applied = HashMap::new();
+        let select = format!("select version, checksum from {}", self.table());
+        let q = sqlx::query(select.as_str());
+        let mut cursor = a.call(q, &mut *con).await;
+        while let Some(row) = cursor.next().await {
+            let row = SqlRunner::row_to_json::<DB>(&row?, &Format::default())?;
+            let version = row["version"].as_i64().unwrap_or_default();
+            let checksum = row["checksum"].as_str().unwrap_or_default().to_string();
+            applied.insert(version, checksum);
+        }
+        drop(cursor);
+
+        for version in applied.keys() {
+            if !migrations.iter().any(|m| m.version == *version) {
+                return Err(SqlMigrateError::Missing(*version));
+            }
+        }
+        for m in &migrations {
+            match applied.get(&m.version) {
+                Some(checksum) if *checksum != m.checksum => {
+                    return Err(SqlMigrateError::ChecksumMismatch(m.version))
+                }
+                _ => {}
+            }
+        }
+
+        let mut done = vec![];
+        for m in migrations
+            .iter()
+            .filter(|m| !applied.contains_key(&m.version))
+        {
+            let mut tx = con.begin().await?;
+            // a plain string runs unprepared, so a file may hold several statements
+            (&mut *tx).execute(m.sql.as_str()).await?;
+            (&mut *tx)
+                .execute(
+                    format!(
+                        "insert into {} (version, name, checksum) values ({}, '{}', '{}')",
+                        self.table(),
+                        m.version,
+                        m.name.replace('\'', "''"),
+                        m.checksum
+                    )
+                    .as_str(),
+                )
+                .await?;
+            tx.commit().await?;
+            done.push(m.version);
+        }
+        Ok(done)
+    }
+}
+
+fn upgrade<DB: sqlx::Database>(w: &Weak<Sql<DB>>) -> Result<Arc<Sql<DB>>, SqlMigrateError> {
+    w.upgrade().ok_or(SqlMigrateError::NotApplied)
+}
+
+/// Reads the `.sql` files of `dir` sorted by version.
+pub fn load(dir: &Path) -> Result<Vec<Migration>, SqlMigrateError> {
+    let mut migrations = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("sql") {
+            continue;
+        }
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let (version, name) =
+            parse_name(&stem).ok_or_else(|| SqlMigrateError::InvalidName(stem.clone()))?;
+
+        let sql = std::fs::read_to_string(&path)?;
+        let checksum = format!("{:x}", Sha256::digest(sql.as_bytes()));
+        migrations.push(Migration {
+            version,
+            name,
+            sql,
+            checksum,
+        });
+    }
+
+    migrations.sort_by_key(|m| m.version);
+    for pair in migrations.windows(2) {
+        if pair[0].version == pair[1].version {
+            return Err(SqlMigrateError::Duplicate(pair[0].version));
+        }
+    }
+    Ok(migrations)
+}
+
+fn parse_name(stem: &str) -> Option<(i64, String)> {
+    let digits = stem.chars().take_while(|c| c.is_ascii_digit()).count();
+    let version = stem[..digits].parse().ok()?;
+    let name = stem[digits..].trim_start_matches('_');
+    Some((version, name.to_string()))
+}
+
+#[async_trait]
+impl super::Init for SqlMigrate {
+    async fn init(&self) -> Result<(), OperatorError> {
+        self.migrate()
+            .await
+            .map(|_| ())
+            .map_err(|e| OperatorError::Other(e.into()))
+    }
+}
+
+impl super::Monad<Sql<MySql>> for SqlMigrate {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<Sql<MySql>>) -> Self::Result {
+        self.w = WType::Mysql(w)
+    }
+}
+
+impl super::Monad<Sql<Sqlite>> for SqlMigrate {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<Sql<Sqlite>>) -> Self::Result {
+        self.w = WType::Sqlite(w)
+    }
+}
+
+impl super::Monad<Sql<Postgres>> for SqlMigrate {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<Sql<Postgres>>) -> Self::Result {
+        self.w = WType::Postgres(w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::sql;
+    use super::*;
+    use crate::operator::Monad;
+
+    #[test]
+    fn names() {
+        assert_eq!(
+            parse_name("0001_create_user"),
+            Some((1, "create_user".to_string()))
+        );
+        assert_eq!(parse_name("12"), Some((12, "".to_string())));
+        assert_eq!(parse_name("create_user"), None);
+    }
+
+    #[async_std::test]
+    async fn migrate_sqlite() {
+        let dir = std::env::temp_dir().join(format!("sql_migrate_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("0001_user.sql"), "create table user (id integer);").unwrap();
+        std::fs::write(
+            dir.join("0002_seed.sql"),
+            "insert into user values (1); insert into user values (2);",
+        )
+        .unwrap();
+
+        let sql = Arc::new(
+            sql::Sql::<Sqlite>::new(sql::Config {
+                dsn: "sqlite::memory:".to_string(),
+                ..Default::default()
+            })
+            .await,
+        );
+        let mut m = SqlMigrate::new(Config {
+            dir: dir.to_string_lossy().to_string(),
+            table: None,
+        });
+        m.apply(Arc::downgrade(&sql));
+
+        assert_eq!(m.migrate().await.unwrap(), vec![1, 2]);
+        assert_eq!(m.migrate().await.unwrap(), Vec::<i64>::new());
+
+        std::fs::write(dir.join("0002_seed.sql"), "insert into user values (3);").unwrap();
+        assert!(matches!(
+            m.migrate().await,
+            Err(SqlMigrateError::ChecksumMismatch(2))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,488 @@
+use super::cache::Cache;
+use super::sql::param::{Input, Origin, Param, ParamError, Statement, Type};
+use super::sql::Sql;
+use super::sql_runner::{Backend, Format, SqlRunner};
+use super::{OperatorError, Sink};
+use async_std::stream::StreamExt;
+use async_std::sync::{Arc, Weak};
+use async_std::task;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sqlx::mysql::MySql;
+use sqlx::postgres::Postgres;
+use sqlx::sqlite::Sqlite;
+use sqlx::Executor;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Polls a table for rows past its high-water mark, a column that only grows such as an
+/// auto increment id or an `updated_at`, and emits each of them to its sinks.
+///
+/// The mark is only moved past a row once every sink took it, and is saved after each
+/// poll, so a row is emitted at least once: again after a failed sink or a restart.
+pub struct SqlPoller {
+    w: WType,
+    sinks: Vec<Weak<dyn Sink>>,
+    config: Config,
+    first: Statement,
+    next: Statement,
+    params: Vec<Param>,
+}
+
+enum WType {
+    Mysql(Weak<Sql<MySql>>),
+    Sqlite(Weak<Sql<Sqlite>>),
+    Postgres(Weak<Sql<Postgres>>),
+    None,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct Config {
+    pub table: String,
+    /// the high-water mark, unique unless `key` is set
+    pub column: String,
+    #[serde(rename = "type", default)]
+    pub ty: Type,
+    /// a unique column, such as the primary key, ordering the rows that share a value of
+    /// `column`; the mark is then `[column, key]` of the last row
+    #[serde(default)]
+    pub key: Option<String>,
+    #[serde(rename = "key_type", default)]
+    pub key_ty: Type,
+    pub interval_ms: u64,
+    /// rows fetched per query, a poll keeps querying until it gets fewer
+    pub batch_size: u64,
+    /// where the mark is saved, kept in memory only when unset
+    #[serde(default)]
+    pub state_file: Option<String>,
+    #[serde(flatten)]
+    pub format: Format,
+}
+
+#[derive(Error, Debug)]
+pub enum SqlPollerError {
+    #[error("sql is not applied")]
+    NotApplied,
+    #[error(transparent)]
+    Param(#[from] ParamError),
+    #[error(transparent)]
+    Sql(#[from] sqlx::Error),
+    #[error("row has no `{0}`")]
+    MissingMark(String),
+    #[error("mark {0} is not a `[column, key]` pair")]
+    InvalidMark(String),
+    #[error("sink faild: {0}")]
+    Sink(OperatorError),
+    #[error("state file: {0}")]
+    State(#[from] std::io::Error),
+}
+
+const MARK: &str = "mark";
+const MARK_KEY: &str = "mark_key";
+
+impl SqlPoller {
+    pub fn new(config: Config) -> Self {
+        let name_ok = |s: &str, dot: bool| {
+            !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || (dot && c == '.'))
+        };
+        if !name_ok(&config.table, true)
+            || !name_ok(&config.column, false)
+            || !config.key.as_deref().map_or(true, |k| name_ok(k, false))
+        {
+            panic!("invalid sql_poller config: table, column and key must be plain names");
+        }
+        if config.interval_ms == 0 || config.batch_size == 0 {
+            panic!("invalid sql_poller config: interval_ms and batch_size must be positive");
+        }
+
+        let param = |name: &str, ty: Type| Param {
+            name: name.to_string(),
+            from: Origin::Output,
+            key: Some(name.to_string()),
+            ty,
+            default: None,
+            required: true,
+        };
+        let params = vec![param(MARK, config.ty), param(MARK_KEY, config.key_ty)];
+        let order = match &config.key {
+            Some(key) => format!("{}, {}", config.column, key),
+            None => config.column.clone(),
+        };
+        let select = |filter: &str| {
+            let sql = format!(
+                "select * from {} {} order by {} limit {}",
+                config.table, filter, order, config.batch_size
+            );
+            match Statement::new(sql.as_str(), &params) {
+                Ok(s) => s,
+                Err(err) => panic!("invalid sql_poller config: {}", err),
+            }
+        };
+        let first = select("");
+        // a batch may end within a run of equal marks, the key resumes it
+        let next = match &config.key {
+            Some(key) => select(
+                format!(
+                    "where {c} > :{m} or ({c} = :{m} and {k} > :{mk})",
+                    c = config.column,
+                    k = key,
+                    m = MARK,
+                    mk = MARK_KEY
+                )
+                .as_str(),
+            ),
+            None => select(format!("where {} > :{}", config.column, MARK).as_str()),
+        };
+
+        SqlPoller {
+            w: WType::None,
+            sinks: vec![],
+            config,
+            first,
+            next,
+            params,
+        }
+    }
+
+    /// Emits the rows past `mark` and returns how many, `mark` is moved up to the last row
+    /// every sink took, also when a sink fails.
+    pub async fn poll(
+        &self,
+        mark: &mut Option<JsonValue>,
+    ) -> std::result::Result<usize, SqlPollerError> {
+        match &self.w {
+            WType::Mysql(w) => self.poll_rows(upgrade(w)?, mark).await,
+            WType::Sqlite(w) => self.poll_rows(upgrade(w)?, mark).await,
+            WType::Postgres(w) => self.poll_rows(upgrade(w)?, mark).await,
+            WType::None => Err(SqlPollerError::NotApplied),
+        }
+    }
+
+    async fn poll_rows<DB: Backend>(
+        &self,
+        a: Arc<Sql<DB>>,
+        mark: &mut Option<JsonValue>,
+    ) -> std::result::Result<usize, SqlPollerError>
+    where
+        for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    {
+        let mut emitted = 0;
+        loop {
+            let mut input = Input::default();
+            let statement = match mark {
+                Some(v) => {
+                    self.bind_mark(v, &mut input)?;
+                    &self.next
+                }
+                None => &self.first,
+            };
+            let args = DB::args(statement, &self.params, &input)?;
+
+            let mut rows = vec![];
+            {
+                let mut con = a.get_reader().await?;
+                let q = args.iter().fold(sqlx::query(DB::sql(statement)), DB::bind);
+                let mut cursor = a.call(q, &mut *con).await;
+                while let Some(row) = cursor.next().await {
+                    rows.push(SqlRunner::row_to_json::<DB>(&row?, &self.config.format)?);
+                }
+            }
+
+            let fetched = rows.len() as u64;
+            for row in rows {
+                let value = self.mark_of(&row)?;
+                self.emit(&row).await?;
+                *mark = Some(value);
+                emitted += 1;
+            }
+            if fetched < self.config.batch_size {
+                return Ok(emitted);
+            }
+        }
+    }
+
+    fn bind_mark(
+        &self,
+        mark: &JsonValue,
+        input: &mut Input,
+    ) -> std::result::Result<(), SqlPollerError> {
+        let (value, key) = match (&self.config.key, mark.as_array().map(Vec::as_slice)) {
+            (None, _) => (mark, None),
+            (Some(_), Some([value, key])) => (value, Some(key)),
+            (Some(_), _) => return Err(SqlPollerError::InvalidMark(mark.to_string())),
+        };
+        input.outputs.insert(MARK.to_string(), value.clone());
+        if let Some(key) = key {
+            input.outputs.insert(MARK_KEY.to_string(), key.clone());
+        }
+        Ok(())
+    }
+
+    fn mark_of(&self, row: &JsonValue) -> std::result::Result<JsonValue, SqlPollerError> {
+        let get = |column: &str| match row.get(column) {
+            Some(v) if !v.is_null() => Ok(v.clone()),
+            _ => Err(SqlPollerError::MissingMark(column.to_string())),
+        };
+        let value = get(&self.config.column)?;
+        match &self.config.key {
+            Some(key) => Ok(JsonValue::from(vec![value, get(key)?])),
+            None => Ok(value),
+        }
+    }
+
+    async fn emit(&self, row: &JsonValue) -> std::result::Result<(), SqlPollerError> {
+        for sink in &self.sinks {
+            // a dropped sink has nothing left to deliver to
+            if let Some(sink) = sink.upgrade() {
+                sink.emit(row).await.map_err(SqlPollerError::Sink)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn load(&self) -> std::result::Result<Option<JsonValue>, SqlPollerError> {
+        let path = match &self.config.state_file {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        if !async_std::path::Path::new(path).exists().await {
+            return Ok(None);
+        }
+        let state: JsonValue = serde_json::from_slice(&async_std::fs::read(path).await?)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        Ok(state.get(MARK).cloned().filter(|v| !v.is_null()))
+    }
+
+    async fn save(&self, mark: &Option<JsonValue>) -> std::result::Result<(), SqlPollerError> {
+        if let Some(path) = &self.config.state_file {
+            let mut state = serde_json::Map::new();
+            state.insert(MARK.to_string(), mark.clone().unwrap_or(JsonValue::Null));
+            // replaced in one step, a crash leaves either the old or the new mark
+            let tmp = format!("{}.tmp", path);
+            async_std::fs::write(&tmp, JsonValue::Object(state).to_string()).await?;
+            async_std::fs::rename(&tmp, path).await?;
+        }
+        Ok(())
+    }
+}
+
+fn upgrade<DB: sqlx::Database>(
+    w: &Weak<Sql<DB>>,
+) -> std::result::Result<Arc<Sql<DB>>, SqlPollerError> {
+    w.upgrade().ok_or(SqlPollerError::NotApplied)
+}
+
+#[async_trait]
+impl super::Source for SqlPoller {
+    async fn start(&self) -> Result<(), OperatorError> {
+        let mut mark = self.load().await.map_err(anyhow::Error::from)?;
+        loop {
+            let before = mark.clone();
+            if let Err(err) = self.poll(&mut mark).await {
+                log::error!("sql_poller {} faild: {}", self.config.table, err);
+            }
+            if mark != before {
+                if let Err(err) = self.save(&mark).await {
+                    log::error!("sql_poller {} save mark faild: {}", self.config.table, err);
+                }
+            }
+            task::sleep(Duration::from_millis(self.config.interval_ms)).await;
+        }
+    }
+}
+
+impl super::Monad<Sql<MySql>> for SqlPoller {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<Sql<MySql>>) -> Self::Result {
+        self.w = WType::Mysql(w)
+    }
+}
+
+impl super::Monad<Sql<Sqlite>> for SqlPoller {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<Sql<Sqlite>>) -> Self::Result {
+        self.w = WType::Sqlite(w)
+    }
+}
+
+impl super::Monad<Sql<Postgres>> for SqlPoller {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<Sql<Postgres>>) -> Self::Result {
+        self.w = WType::Postgres(w)
+    }
+}
+
+impl super::Monad<Cache> for SqlPoller {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<Cache>) -> Self::Result {
+        self.sinks.push(w);
+    }
+}
+
+impl super::Monad<SqlRunner> for SqlPoller {
+    type Result = ();
+
+    fn apply(&mut self, w: Weak<SqlRunner>) -> Self::Result {
+        self.sinks.push(w);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::sql;
+    use super::*;
+    use crate::operator::Monad;
+    use async_std::sync::Mutex;
+
+    struct Collect {
+        rows: Mutex<Vec<JsonValue>>,
+        fail_at: Option<i64>,
+    }
+
+    #[async_trait]
+    impl Sink for Collect {
+        async fn emit(&self, row: &JsonValue) -> Result<(), OperatorError> {
+            if row["id"].as_i64() == self.fail_at {
+                return Err(anyhow::anyhow!("sink is down").into());
+            }
+            self.rows.lock().await.push(row.clone());
+            Ok(())
+        }
+    }
+
+    #[async_std::test]
+    async fn poll() {
+        let sql = Arc::new(
+            sql::Sql::<Sqlite>::new(sql::Config {
+                dsn: "sqlite::memory:".to_string(),
+                ..Default::default()
+            })
+            .await,
+        );
+        let mut con = sql.get_executor().await.unwrap();
+        sqlx::query("create table event (id integer primary key, name text)")
+            .execute(&mut con)
+            .await
+            .unwrap();
+        sqlx::query("insert into event (name) values ('a'), ('b'), ('c')")
+            .execute(&mut con)
+            .await
+            .unwrap();
+        drop(con);
+
+        let mut poller = SqlPoller::new(Config {
+            table: "event".to_string(),
+            column: "id".to_string(),
+            ty: Type::Int,
+            interval_ms: 10,
+            batch_size: 2,
+            ..Default::default()
+        });
+        poller.apply(Arc::downgrade(&sql));
+        let sink = Arc::new(Collect {
+            rows: Mutex::new(vec![]),
+            fail_at: Some(3),
+        });
+        poller.sinks.push(Arc::downgrade(&sink));
+
+        // the mark stays before the row the sink failed on
+        let mut mark = None;
+        assert!(poller.poll(&mut mark).await.is_err());
+        assert_eq!(mark, Some(JsonValue::from(2)));
+        assert_eq!(sink.rows.lock().await.len(), 2);
+
+        let sink = Arc::new(Collect {
+            rows: Mutex::new(vec![]),
+            fail_at: None,
+        });
+        poller.sinks = vec![Arc::downgrade(&sink) as Weak<dyn Sink>];
+        assert_eq!(poller.poll(&mut mark).await.unwrap(), 1);
+        assert_eq!(
+            *sink.rows.lock().await,
+            vec![serde_json::json!({"id": 3, "name": "c"})]
+        );
+        assert_eq!(poller.poll(&mut mark).await.unwrap(), 0);
+    }
+
+    #[async_std::test]
+    async fn equal_marks() {
+        let sql = Arc::new(
+            sql::Sql::<Sqlite>::new(sql::Config {
+                dsn: "sqlite::memory:".to_string(),
+                ..Default::default()
+            })
+            .await,
+        );
+        let mut con = sql.get_executor().await.unwrap();
+        sqlx::query("create table change_log (id integer primary key, at integer)")
+            .execute(&mut con)
+            .await
+            .unwrap();
+        sqlx::query("insert into change_log (at) values (1), (2), (2), (2), (3)")
+            .execute(&mut con)
+            .await
+            .unwrap();
+        drop(con);
+
+        // each batch of 2 ends within the run of `at = 2`
+        let mut poller = SqlPoller::new(Config {
+            table: "change_log".to_string(),
+            column: "at".to_string(),
+            ty: Type::Int,
+            key: Some("id".to_string()),
+            key_ty: Type::Int,
+            interval_ms: 10,
+            batch_size: 2,
+            ..Default::default()
+        });
+        poller.apply(Arc::downgrade(&sql));
+        let sink = Arc::new(Collect {
+            rows: Mutex::new(vec![]),
+            fail_at: None,
+        });
+        poller.sinks.push(Arc::downgrade(&sink));
+
+        let mut mark = None;
+        assert_eq!(poller.poll(&mut mark).await.unwrap(), 5);
+        let ids: Vec<JsonValue> = sink
+            .rows
+            .lock()
+            .await
+            .iter()
+            .map(|r| r["id"].clone())
+            .collect();
+        assert_eq!(JsonValue::from(ids), serde_json::json!([1, 2, 3, 4, 5]));
+        assert_eq!(mark, Some(serde_json::json!([3, 5])));
+        assert_eq!(poller.poll(&mut mark).await.unwrap(), 0);
+
+        assert!(matches!(
+            poller.poll(&mut Some(JsonValue::from(2))).await,
+            Err(SqlPollerError::InvalidMark(_))
+        ));
+    }
+
+    #[async_std::test]
+    async fn state() {
+        let path = std::env::temp_dir().join("sql_poller_state.json");
+        let _ = std::fs::remove_file(&path);
+        let poller = SqlPoller::new(Config {
+            table: "event".to_string(),
+            column: "id".to_string(),
+            interval_ms: 10,
+            batch_size: 10,
+            state_file: Some(path.to_string_lossy().to_string()),
+            ..Default::default()
+        });
+
+        assert_eq!(poller.load().await.unwrap(), None);
+        poller.save(&Some(JsonValue::from(7))).await.unwrap();
+        assert_eq!(poller.load().await.unwrap(), Some(JsonValue::from(7)));
+    }
+}
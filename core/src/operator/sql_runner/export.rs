@@ -0,0 +1,296 @@
+use arrow::array::{
+    ArrayRef, BinaryBuilder, BooleanBuilder, Float64Builder, Int64Builder, StringBuilder,
+    UInt64Builder,
+};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::error::ArrowError;
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::errors::ParquetError;
+use parquet::file::writer::InMemoryWriteableCursor;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// The format of the response body. Other than `Json`, the rows are flat and typed by the
+/// columns of the sql, so they need the default `Array` shape without `nest`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum Export {
+    Json,
+    /// a header row, then one row per record
+    Csv,
+    /// an Arrow IPC stream holding a single record batch
+    ArrowIpc,
+    Parquet,
+}
+
+impl Default for Export {
+    fn default() -> Self {
+        Export::Json
+    }
+}
+
+/// The type of a column in Arrow and Parquet, derived by each backend from its column types.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColumnType {
+    Bool,
+    Int,
+    UInt,
+    Float,
+    String,
+    /// base64 in json, raw bytes in Arrow and Parquet
+    Binary,
+}
+
+pub struct ExportColumn {
+    /// the field name in the rendered rows
+    pub name: String,
+    pub ty: ColumnType,
+}
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    Arrow(#[from] ArrowError),
+    #[error(transparent)]
+    Parquet(#[from] ParquetError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl Export {
+    /// The first media type of an `Accept` header that is an export, in the order given.
+    pub fn from_accept(accept: &str) -> Option<Export> {
+        accept.split(',').find_map(|media| {
+            let media = media.split(';').next().unwrap_or("").trim().to_lowercase();
+            match media.as_str() {
+                "application/json" => Some(Export::Json),
+                "text/csv" => Some(Export::Csv),
+                "application/vnd.apache.arrow.stream" => Some(Export::ArrowIpc),
+                "application/vnd.apache.parquet" | "application/x-parquet" => Some(Export::Parquet),
+                _ => None,
+            }
+        })
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Export::Json => "application/json",
+            Export::Csv => "text/csv",
+            Export::ArrowIpc => "application/vnd.apache.arrow.stream",
+            Export::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    /// `rows` are the rendered json rows, which have a field per column.
+    pub fn encode(
+        &self,
+        columns: &[ExportColumn],
+        rows: &[JsonValue],
+    ) -> Result<Vec<u8>, ExportError> {
+        match self {
+            Export::Json => Ok(serde_json::to_vec(rows)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?),
+            Export::Csv => csv(columns, rows),
+            Export::ArrowIpc => {
+                let batch = batch(columns, rows)?;
+                let mut bytes = vec![];
+                {
+                    let mut writer = StreamWriter::try_new(&mut bytes, &batch.schema())?;
+                    writer.write(&batch)?;
+                    writer.finish()?;
+                }
+                Ok(bytes)
+            }
+            Export::Parquet => {
+                let batch = batch(columns, rows)?;
+                let cursor = InMemoryWriteableCursor::default();
+                let mut writer = ArrowWriter::try_new(cursor.clone(), batch.schema(), None)?;
+                writer.write(&batch)?;
+                writer.close()?;
+                drop(writer);
+                cursor
+                    .into_inner()
+                    .ok_or_else(|| ParquetError::General("parquet writer is in use".to_string()))
+                    .map_err(ExportError::from)
+            }
+        }
+    }
+}
+
+fn value<'a>(row: &'a JsonValue, column: &ExportColumn) -> &'a JsonValue {
+    row.get(column.name.as_str()).unwrap_or(&JsonValue::Null)
+}
+
+fn csv(columns: &[ExportColumn], rows: &[JsonValue]) -> Result<Vec<u8>, ExportError> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(columns.iter().map(|c| c.name.as_str()))?;
+    for row in rows {
+        // null is an empty field
+        writer.write_record(columns.iter().map(|c| match value(row, c) {
+            JsonValue::Null => String::new(),
+            JsonValue::String(s) => s.clone(),
+            v => v.to_string(),
+        }))?;
+    }
+    writer.into_inner().map_err(|e| e.into_error().into())
+}
+
+fn batch(columns: &[ExportColumn], rows: &[JsonValue]) -> Result<RecordBatch, ArrowError> {
+    let fields = columns
+        .iter()
+        .map(|c| Field::new(c.name.as_str(), c.ty.data_type(), true))
+        .collect();
+    let arrays = columns
+        .iter()
+        .map(|c| array(c, rows))
+        .collect::<Result<Vec<_>, _>>()?;
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)
+}
+
+impl ColumnType {
+    fn data_type(&self) -> DataType {
+        match self {
+            ColumnType::Bool => DataType::Boolean,
+            ColumnType::Int => DataType::Int64,
+            ColumnType::UInt => DataType::UInt64,
+            ColumnType::Float => DataType::Float64,
+            ColumnType::String => DataType::Utf8,
+            ColumnType::Binary => DataType::Binary,
+        }
+    }
+}
+
+// a value of another json type than the column's is null
+fn array(column: &ExportColumn, rows: &[JsonValue]) -> Result<ArrayRef, ArrowError> {
+    let values = rows.iter().map(|row| value(row, column));
+    Ok(match column.ty {
+        ColumnType::Bool => {
+            let mut b = BooleanBuilder::new(rows.len());
+            for v in values {
+                match v.as_bool() {
+                    Some(v) => b.append_value(v)?,
+                    None => b.append_null()?,
+                }
+            }
+            Arc::new(b.finish())
+        }
+        ColumnType::Int => {
+            let mut b = Int64Builder::new(rows.len());
+            for v in values {
+                match v.as_i64() {
+                    Some(v) => b.append_value(v)?,
+                    None => b.append_null()?,
+                }
+            }
+            Arc::new(b.finish())
+        }
+        ColumnType::UInt => {
+            let mut b = UInt64Builder::new(rows.len());
+            for v in values {
+                match v.as_u64() {
+                    Some(v) => b.append_value(v)?,
+                    None => b.append_null()?,
+                }
+            }
+            Arc::new(b.finish())
+        }
+        ColumnType::Float => {
+            let mut b = Float64Builder::new(rows.len());
+            for v in values {
+                match v.as_f64() {
+                    Some(v) => b.append_value(v)?,
+                    None => b.append_null()?,
+                }
+            }
+            Arc::new(b.finish())
+        }
+        ColumnType::String => {
+            let mut b = StringBuilder::new(rows.len());
+            for v in values {
+                match v {
+                    JsonValue::Null => b.append_null()?,
+                    JsonValue::String(s) => b.append_value(s)?,
+                    v => b.append_value(&v.to_string())?,
+                }
+            }
+            Arc::new(b.finish())
+        }
+        ColumnType::Binary => {
+            let mut b = BinaryBuilder::new(rows.len());
+            for v in values {
+                match v.as_str().map(base64::decode) {
+                    Some(Ok(bytes)) => b.append_value(&bytes)?,
+                    Some(Err(err)) => return Err(ArrowError::ParseError(err.to_string())),
+                    None => b.append_null()?,
+                }
+            }
+            Arc::new(b.finish())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn columns() -> Vec<ExportColumn> {
+        vec![
+            ExportColumn {
+                name: "id".to_string(),
+                ty: ColumnType::Int,
+            },
+            ExportColumn {
+                name: "name".to_string(),
+                ty: ColumnType::String,
+            },
+            ExportColumn {
+                name: "raw".to_string(),
+                ty: ColumnType::Binary,
+            },
+        ]
+    }
+
+    #[test]
+    fn accept() {
+        assert_eq!(
+            Export::from_accept("text/html, text/csv;q=0.9"),
+            Some(Export::Csv)
+        );
+        assert_eq!(
+            Export::from_accept("application/x-parquet"),
+            Some(Export::Parquet)
+        );
+        assert_eq!(Export::from_accept("*/*"), None);
+    }
+
+    #[test]
+    fn encode() {
+        let rows = vec![
+            json!({"id": 1, "name": "a, \"b\"", "raw": "AQL/"}),
+            json!({"id": 2, "name": null, "raw": null}),
+        ];
+
+        let csv = Export::Csv.encode(&columns(), &rows).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "id,name,raw\n1,\"a, \"\"b\"\"\",AQL/\n2,,\n"
+        );
+
+        let batch = batch(&columns(), &rows).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.schema().field(2).data_type(), &DataType::Binary);
+
+        let ipc = Export::ArrowIpc.encode(&columns(), &rows).unwrap();
+        let reader = arrow::ipc::reader::StreamReader::try_new(std::io::Cursor::new(ipc)).unwrap();
+        assert_eq!(reader.schema().fields().len(), 3);
+
+        let parquet = Export::Parquet.encode(&columns(), &rows).unwrap();
+        assert_eq!(&parquet[..4], b"PAR1");
+    }
+}
@@ -0,0 +1,262 @@
+use crate::operator::Principal;
+use hmac::{Hmac, Mac, NewMac};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sha2::Sha256;
+
+/// Column masks applied to every row before it is rendered.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Masking {
+    #[serde(default)]
+    pub masks: Vec<Mask>,
+    /// callers with this role see the columns unmasked
+    #[serde(default)]
+    pub unmask_role: Option<String>,
+    /// secret key of the `Hash` strategy, required when it is used
+    #[serde(default)]
+    pub hash_key: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Mask {
+    /// column name, `*` matches any run of characters, e.g. `*email*`; case insensitive
+    pub column: String,
+    pub strategy: Strategy,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub enum Strategy {
+    /// leaves the column out
+    Drop,
+    /// hex hmac-sha256 of the value under `hash_key`, so equal values can still be matched
+    /// but short values can't be found by hashing guesses
+    Hash,
+    /// keeps the first and last characters and replaces the rest with `*`
+    Partial {
+        #[serde(default)]
+        keep_start: usize,
+        #[serde(default)]
+        keep_end: usize,
+    },
+    Null,
+}
+
+impl Masking {
+    pub fn validate(&self) -> Result<(), String> {
+        let hashed = self
+            .masks
+            .iter()
+            .any(|m| matches!(m.strategy, Strategy::Hash));
+        match &self.hash_key {
+            None if hashed => Err("hash_key is required by the Hash strategy".to_string()),
+            Some(key) if key.is_empty() => Err("hash_key is empty".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn applies(&self, principal: Option<&Principal>) -> bool {
+        match (&self.unmask_role, principal) {
+            (Some(role), Some(p)) => !p.roles.contains(role),
+            _ => true,
+        }
+    }
+
+    /// The strategy of the first mask matching `column`.
+    pub fn strategy(&self, column: &str) -> Option<&Strategy> {
+        self.masks
+            .iter()
+            .find(|m| glob(&m.column, column))
+            .map(|m| &m.strategy)
+    }
+
+    pub fn row(&self, row: JsonValue) -> JsonValue {
+        if self.masks.is_empty() {
+            return row;
+        }
+
+        match row {
+            JsonValue::Object(columns) => {
+                let mut m = serde_json::Map::new();
+                for (name, v) in columns {
+                    match self.strategy(&name) {
+                        Some(Strategy::Drop) => {}
+                        Some(strategy) => {
+                            let key = self.hash_key.as_deref().unwrap_or_default();
+                            m.insert(name, strategy.apply(v, key.as_bytes()));
+                        }
+                        None => {
+                            m.insert(name, v);
+                        }
+                    }
+                }
+                JsonValue::Object(m)
+            }
+            other => other,
+        }
+    }
+}
+
+impl Strategy {
+    fn apply(&self, v: JsonValue, key: &[u8]) -> JsonValue {
+        let s = match &v {
+            JsonValue::Null => return v,
+            JsonValue::String(s) => s.clone(),
+            _ => v.to_string(),
+        };
+
+        match self {
+            Strategy::Drop | Strategy::Null => JsonValue::Null,
+            Strategy::Hash => JsonValue::from(hmac(key, s.as_bytes())),
+            Strategy::Partial {
+                keep_start,
+                keep_end,
+            } => {
+                let chars: Vec<char> = s.chars().collect();
+                // a value too short to hide anything is masked entirely
+                if keep_start + keep_end >= chars.len() {
+                    return JsonValue::from("*".repeat(chars.len()));
+                }
+                let masked: String = chars
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| {
+                        if i < *keep_start || i >= chars.len() - keep_end {
+                            *c
+                        } else {
+                            '*'
+                        }
+                    })
+                    .collect();
+                JsonValue::from(masked)
+            }
+        }
+    }
+}
+
+fn hmac(key: &[u8], value: &[u8]) -> String {
+    // any key length is accepted
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("hmac key");
+    mac.update(value);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn glob(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let name = name.to_lowercase();
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == name;
+    }
+
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if name.len() < first.len() + last.len() || !name.starts_with(first) || !name.ends_with(last) {
+        return false;
+    }
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn globs() {
+        assert!(glob("email", "EMAIL"));
+        assert!(glob("*email*", "user_email_addr"));
+        assert!(glob("pass*", "password_hash"));
+        assert!(glob("*_hash", "password_hash"));
+        assert!(glob("a*b*c", "axxbyyc"));
+        assert!(!glob("a*b*c", "axxcyyb"));
+        assert!(!glob("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn masks() {
+        let masking = Masking {
+            masks: vec![
+                Mask {
+                    column: "password*".to_string(),
+                    strategy: Strategy::Drop,
+                },
+                Mask {
+                    column: "*email".to_string(),
+                    strategy: Strategy::Hash,
+                },
+                Mask {
+                    column: "phone".to_string(),
+                    strategy: Strategy::Partial {
+                        keep_start: 3,
+                        keep_end: 2,
+                    },
+                },
+                Mask {
+                    column: "ssn".to_string(),
+                    strategy: Strategy::Null,
+                },
+            ],
+            unmask_role: Some("admin".to_string()),
+            hash_key: Some("key".to_string()),
+        };
+        assert!(masking.validate().is_ok());
+
+        let row = masking.row(json!({
+            "id": 1,
+            "password_hash": "x",
+            "email": "a@b.c",
+            "phone": "13800001234",
+            "ssn": "123",
+        }));
+        assert_eq!(
+            row,
+            json!({
+                "id": 1,
+                "email": hmac(b"key", b"a@b.c"),
+                "phone": "138******34",
+                "ssn": null,
+            })
+        );
+
+        let admin = Principal {
+            roles: vec!["admin".to_string()],
+            ..Default::default()
+        };
+        assert!(masking.applies(None));
+        assert!(masking.applies(Some(&Principal::default())));
+        assert!(!masking.applies(Some(&admin)));
+    }
+
+    #[test]
+    fn hash() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            hmac(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+
+        let mut masking = Masking {
+            masks: vec![Mask {
+                column: "email".to_string(),
+                strategy: Strategy::Hash,
+            }],
+            ..Default::default()
+        };
+        assert!(masking.validate().is_err());
+        masking.hash_key = Some("other".to_string());
+        assert_ne!(
+            masking.row(json!({"email": "a@b.c"}))["email"],
+            json!(hmac(b"key", b"a@b.c"))
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use super::{Backend, ColumnType, DateTimeFormat, DecimalFormat, Format};
+use crate::operator::sql::param::Value;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde_json::Value as JsonValue;
+use sqlx::decode::Decode;
+use sqlx::mysql::{MySql, MySqlArguments, MySqlDone, MySqlTypeInfo, MySqlValueRef};
+use sqlx::query::Query;
+use sqlx::{TypeInfo, ValueRef};
+
+impl Backend for MySql {
+    fn bind<'q>(
+        q: Query<'q, MySql, MySqlArguments>,
+        value: &Value,
+    ) -> Query<'q, MySql, MySqlArguments> {
+        match value.clone() {
+            Value::Null => q.bind(None::<String>),
+            Value::Int(v) => q.bind(v),
+            Value::Float(v) => q.bind(v),
+            Value::String(v) => q.bind(v),
+            Value::Bool(v) => q.bind(v),
+        }
+    }
+
+    fn get_as_json_value(
+        vref: MySqlValueRef<'_>,
+        type_info: &MySqlTypeInfo,
+        format: &Format,
+    ) -> Result<JsonValue, sqlx::Error> {
+        if vref.is_null() {
+            return Ok(JsonValue::Null);
+        }
+
+        Ok(match type_info.name() {
+            "BOOLEAN" => JsonValue::from(decode::<bool>(vref)?),
+            "TINYINT UNSIGNED" => JsonValue::from(decode::<u8>(vref)?),
+            "TINYINT" => JsonValue::from(decode::<i8>(vref)?),
+            "SMALLINT UNSIGNED" => JsonValue::from(decode::<u16>(vref)?),
+            "SMALLINT" => JsonValue::from(decode::<i16>(vref)?),
+            "MEDIUMINT UNSIGNED" | "INT UNSIGNED" => JsonValue::from(decode::<u32>(vref)?),
+            "MEDIUMINT" | "INT" => JsonValue::from(decode::<i32>(vref)?),
+            "BIGINT UNSIGNED" => JsonValue::from(decode::<u64>(vref)?),
+            "BIGINT" => JsonValue::from(decode::<i64>(vref)?),
+            "YEAR" => JsonValue::from(decode::<u16>(vref)?),
+            "FLOAT" => JsonValue::from(decode::<f32>(vref)?),
+            "DOUBLE" => JsonValue::from(decode::<f64>(vref)?),
+            "DECIMAL" => format.decimal.to_json(decode::<&str>(vref)?),
+            "BIT" => JsonValue::from(bits_to_u64(decode::<&[u8]>(vref)?)),
+            "TIMESTAMP" | "DATETIME" => format
+                .datetime
+                .datetime_to_json(decode::<NaiveDateTime>(vref)?),
+            "DATE" => format.datetime.date_to_json(decode::<NaiveDate>(vref)?),
+            "TIME" => JsonValue::from(time_to_string(decode::<&[u8]>(vref)?)?),
+            "SET" => JsonValue::from(
+                decode::<&str>(vref)?
+                    .split(',')
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>(),
+            ),
+            "JSON" => decode::<JsonValue>(vref)?,
+            "NULL" => JsonValue::Null,
+            "GEOMETRY" | "BINARY" | "VARBINARY" | "TINYBLOB" | "BLOB" | "MEDIUMBLOB"
+            | "LONGBLOB" => JsonValue::from(base64::encode(decode::<&[u8]>(vref)?)),
+            // CHAR, VARCHAR, TEXT and ENUM
+            _ => JsonValue::from(decode::<&str>(vref)?),
+        })
+    }
+
+    fn column_type(type_info: &MySqlTypeInfo, format: &Format) -> ColumnType {
+        // follows the json rendering of `get_as_json_value`
+        match type_info.name() {
+            "BOOLEAN" => ColumnType::Bool,
+            "TINYINT" | "SMALLINT" | "MEDIUMINT" | "INT" | "BIGINT" => ColumnType::Int,
+            "TINYINT UNSIGNED" | "SMALLINT UNSIGNED" | "MEDIUMINT UNSIGNED" | "INT UNSIGNED"
+            | "BIGINT UNSIGNED" | "YEAR" | "BIT" => ColumnType::UInt,
+            "FLOAT" | "DOUBLE" => ColumnType::Float,
+            "DECIMAL" => match format.decimal {
+                DecimalFormat::String => ColumnType::String,
+                DecimalFormat::Number => ColumnType::Float,
+            },
+            "TIMESTAMP" | "DATETIME" | "DATE" => match format.datetime {
+                DateTimeFormat::Iso8601 => ColumnType::String,
+                _ => ColumnType::Int,
+            },
+            "GEOMETRY" | "BINARY" | "VARBINARY" | "TINYBLOB" | "BLOB" | "MEDIUMBLOB"
+            | "LONGBLOB" => ColumnType::Binary,
+            _ => ColumnType::String,
+        }
+    }
+
+    fn last_insert_id(done: &MySqlDone) -> JsonValue {
+        JsonValue::from(done.last_insert_id())
+    }
+}
+
+fn decode<'r, T: Decode<'r, MySql>>(vref: MySqlValueRef<'r>) -> Result<T, sqlx::Error> {
+    T::decode(vref).map_err(sqlx::Error::Decode)
+}
+
+// BIT(n) comes as big-endian bytes
+fn bits_to_u64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, b| (acc << 8) | u64::from(*b))
+}
+
+// TIME is a duration of -838:59:59 to 838:59:59, so it is rendered as `[-]HH:MM:SS[.ffffff]`
+// with as many hour digits as needed. The binary protocol sends a length byte of 0, 8 or 12,
+// then the sign, days, hours, minutes, seconds and microseconds; the text one sends the
+// rendering already.
+fn time_to_string(bytes: &[u8]) -> Result<String, sqlx::Error> {
+    let binary = match bytes.first() {
+        Some(&len) => [0, 8, 12].contains(&len) && bytes.len() == len as usize + 1,
+        None => false,
+    };
+    if !binary {
+        return std::str::from_utf8(bytes)
+            .map(str::to_string)
+            .map_err(|e| sqlx::Error::Decode(e.into()));
+    }
+    if bytes[0] == 0 {
+        return Ok("00:00:00".to_string());
+    }
+
+    let u32_at =
+        |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+    let hours = u64::from(u32_at(2)) * 24 + u64::from(bytes[6]);
+    let mut s = format!(
+        "{}{:02}:{:02}:{:02}",
+        if bytes[1] == 1 { "-" } else { "" },
+        hours,
+        bytes[7],
+        bytes[8]
+    );
+    if bytes[0] == 12 {
+        let micros = u32_at(9);
+        if micros > 0 {
+            s.push_str(&format!(".{:06}", micros));
+        }
+    }
+    Ok(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time() {
+        assert_eq!(time_to_string(&[0]).unwrap(), "00:00:00");
+        // -838:59:59 is 34 days and 22 hours
+        assert_eq!(
+            time_to_string(&[8, 1, 34, 0, 0, 0, 22, 59, 59]).unwrap(),
+            "-838:59:59"
+        );
+        assert_eq!(
+            time_to_string(&[12, 0, 1, 0, 0, 0, 1, 0, 1, 0x20, 0xa1, 0x07, 0]).unwrap(),
+            "25:00:01.500000"
+        );
+        assert_eq!(time_to_string(b"-27:04:05").unwrap(), "-27:04:05");
+    }
+
+    #[test]
+    fn bits() {
+        assert_eq!(bits_to_u64(&[0x05]), 5);
+        assert_eq!(bits_to_u64(&[0x01, 0x02]), 258);
+    }
+}
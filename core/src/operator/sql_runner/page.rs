@@ -0,0 +1,382 @@
+use crate::operator::sql::param::{Input, Origin, Param, ParamError, Statement, Type, Value};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+/// Pages the rows of the sql, which should not have its own `order by` or `limit`.
+/// Rows are ordered by `key` in both modes, so a page holds the same rows on every call.
+///
+/// The page is read from the query string: `limit`, then `offset` or the `cursor`
+/// returned with the previous page. Rows are returned as
+/// `{"rows": [...], "has_more": bool, "next_cursor": string or null, "total": n}`,
+/// `total` only when `count` is set.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Page {
+    pub max_size: u64,
+    /// `max_size` when unset
+    #[serde(default)]
+    pub default_size: Option<u64>,
+    pub key: Key,
+    /// offset pagination, keyset pagination on `key` when unset
+    #[serde(default)]
+    pub offset: bool,
+    #[serde(default)]
+    pub count: bool,
+}
+
+/// A unique column the rows are ordered by.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Key {
+    pub column: String,
+    #[serde(rename = "type", default)]
+    pub ty: Type,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+pub struct Paging {
+    page: Page,
+    params: Vec<Param>,
+    first: Statement,
+    next: Statement,
+    total: Option<Statement>,
+}
+
+pub struct PageRequest {
+    limit: u64,
+    offset: u64,
+    after: Option<JsonValue>,
+}
+
+// internal params, bound from `Input::outputs` after the page is read from the request
+const PAGE: &str = "__page";
+
+fn page_param(name: &str, ty: Type) -> Param {
+    Param {
+        name: format!("{}_{}", PAGE, name),
+        from: Origin::Output,
+        key: Some(format!("{}.{}", PAGE, name)),
+        ty,
+        default: None,
+        required: true,
+    }
+}
+
+impl Paging {
+    pub fn new(sql: &str, params: &[Param], page: Page) -> Result<Paging, ParamError> {
+        if page.max_size == 0 {
+            return Err(ParamError::Invalid {
+                name: "max_size".to_string(),
+                ty: "positive int",
+            });
+        }
+
+        let mut params = params.to_vec();
+        let inner = format!("select * from ({}) as page", sql);
+        let key = &page.key;
+        if key.column.is_empty()
+            || !key
+                .column
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(ParamError::Invalid {
+                name: key.column.clone(),
+                ty: "column",
+            });
+        }
+        let (cmp, dir) = if key.descending {
+            ("<", "desc")
+        } else {
+            (">", "asc")
+        };
+        let (first, next) = if page.offset {
+            let sql = format!(
+                "{} order by {} {} limit :__page_limit offset :__page_offset",
+                inner, key.column, dir
+            );
+            (sql.clone(), sql)
+        } else {
+            params.push(page_param("after", key.ty));
+            (
+                format!(
+                    "{} order by {} {} limit :__page_limit",
+                    inner, key.column, dir
+                ),
+                format!(
+                    "{} where {} {} :__page_after order by {} {} limit :__page_limit",
+                    inner, key.column, cmp, key.column, dir
+                ),
+            )
+        };
+        params.push(page_param("limit", Type::Int));
+        params.push(page_param("offset", Type::Int));
+
+        let total = if page.count {
+            let sql = format!("select count(*) as total from ({}) as page", sql);
+            Some(Statement::new(sql.as_str(), &params)?)
+        } else {
+            None
+        };
+
+        Ok(Paging {
+            first: Statement::new(first.as_str(), &params)?,
+            next: Statement::new(next.as_str(), &params)?,
+            total,
+            params,
+            page,
+        })
+    }
+
+    pub fn request(&self, input: &Input) -> Result<PageRequest, ParamError> {
+        let number = |name: &str| match input.query.get(name) {
+            Some(v) => v
+                .trim()
+                .parse::<u64>()
+                .map(Some)
+                .map_err(|_| invalid(name, "int")),
+            None => Ok(None),
+        };
+
+        let limit = number("limit")?
+            .or(self.page.default_size)
+            .unwrap_or(self.page.max_size)
+            .min(self.page.max_size)
+            .max(1);
+        let mut offset = number("offset")?.unwrap_or(0);
+        let mut after = None;
+
+        if let Some(cursor) = input.query.get("cursor") {
+            let v = base64::decode(cursor)
+                .ok()
+                .and_then(|b| serde_json::from_slice::<JsonValue>(&b).ok())
+                .ok_or_else(|| invalid("cursor", "cursor"))?;
+            match self.page.offset {
+                true => offset = v.as_u64().ok_or_else(|| invalid("cursor", "cursor"))?,
+                false => after = Some(v),
+            }
+        }
+
+        Ok(PageRequest {
+            limit,
+            offset,
+            after,
+        })
+    }
+
+    pub fn statement(&self, req: &PageRequest) -> &Statement {
+        match req.after {
+            Some(_) => &self.next,
+            None => &self.first,
+        }
+    }
+
+    pub fn total(&self) -> Option<&Statement> {
+        self.total.as_ref()
+    }
+
+    /// Args of `statement(req)`, one extra row is fetched to tell if there is a next page.
+    /// `numbered` for the args of `Statement::numbered`.
+    pub fn args(
+        &self,
+        req: &PageRequest,
+        input: &Input,
+        numbered: bool,
+    ) -> Result<Vec<Value>, ParamError> {
+        let mut page = serde_json::Map::new();
+        page.insert("limit".to_string(), JsonValue::from(req.limit + 1));
+        page.insert("offset".to_string(), JsonValue::from(req.offset));
+        if let Some(after) = &req.after {
+            page.insert("after".to_string(), after.clone());
+        }
+
+        let mut outputs = input.outputs.clone();
+        outputs.insert(PAGE.to_string(), JsonValue::Object(page));
+        let input = Input {
+            path: input.path.clone(),
+            query: input.query.clone(),
+            body: input.body.clone(),
+            principal: input.principal.clone(),
+            outputs,
+            tenant: input.tenant.clone(),
+        };
+        args(self.statement(req), &self.params, &input, numbered)
+    }
+
+    pub fn total_args(&self, input: &Input, numbered: bool) -> Result<Vec<Value>, ParamError> {
+        match &self.total {
+            Some(total) => args(total, &self.params, input, numbered),
+            None => Ok(vec![]),
+        }
+    }
+
+    pub fn envelope(
+        &self,
+        req: &PageRequest,
+        rows: JsonValue,
+        total: Option<JsonValue>,
+    ) -> JsonValue {
+        let mut rows = match rows {
+            JsonValue::Array(rows) => rows,
+            _ => vec![],
+        };
+        let has_more = rows.len() as u64 > req.limit;
+        rows.truncate(req.limit as usize);
+
+        let next = match rows.last() {
+            _ if !has_more => None,
+            _ if self.page.offset => Some(JsonValue::from(req.offset + req.limit)),
+            Some(last) => last.get(self.page.key.column.as_str()).cloned(),
+            None => None,
+        };
+
+        let mut m = serde_json::Map::new();
+        m.insert("rows".to_string(), JsonValue::from(rows));
+        m.insert("has_more".to_string(), JsonValue::from(has_more));
+        m.insert(
+            "next_cursor".to_string(),
+            match next {
+                Some(v) => JsonValue::from(base64::encode(v.to_string())),
+                None => JsonValue::Null,
+            },
+        );
+        if let Some(total) = total {
+            // the count query returns a single row
+            let n = total
+                .get(0)
+                .and_then(|row| row.get("total"))
+                .cloned()
+                .unwrap_or(JsonValue::Null);
+            m.insert("total".to_string(), n);
+        }
+        JsonValue::Object(m)
+    }
+}
+
+fn invalid(name: &str, ty: &'static str) -> ParamError {
+    ParamError::Invalid {
+        name: name.to_string(),
+        ty,
+    }
+}
+
+fn args(
+    statement: &Statement,
+    params: &[Param],
+    input: &Input,
+    numbered: bool,
+) -> Result<Vec<Value>, ParamError> {
+    if numbered {
+        statement.numbered_args(params, input)
+    } else {
+        statement.args(params, input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn input(query: &[(&str, &str)]) -> Input {
+        let mut input = Input::default();
+        for (k, v) in query {
+            input.query.insert(k.to_string(), v.to_string());
+        }
+        input
+    }
+
+    #[test]
+    fn offset() {
+        let paging = Paging::new(
+            "select * from user",
+            &[],
+            Page {
+                max_size: 2,
+                key: Key {
+                    column: "id".to_string(),
+                    ..Default::default()
+                },
+                offset: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            paging.first.sql,
+            "select * from (select * from user) as page order by id asc limit ? offset ?"
+        );
+
+        let req = paging
+            .request(&input(&[("limit", "5"), ("offset", "4")]))
+            .unwrap();
+        assert_eq!(
+            paging.args(&req, &Input::default(), false).unwrap(),
+            vec![Value::Int(3), Value::Int(4)]
+        );
+
+        let page = paging.envelope(&req, json!([{"id": 5}, {"id": 6}, {"id": 7}]), None);
+        assert_eq!(page["rows"], json!([{"id": 5}, {"id": 6}]));
+        assert_eq!(page["has_more"], json!(true));
+
+        let cursor = page["next_cursor"].as_str().unwrap().to_string();
+        let req = paging
+            .request(&input(&[("cursor", cursor.as_str())]))
+            .unwrap();
+        assert_eq!(req.offset, 6);
+
+        // without an order the rows of a page may change from call to call
+        assert!(Paging::new(
+            "select * from user",
+            &[],
+            Page {
+                max_size: 2,
+                offset: true,
+                ..Default::default()
+            },
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn keyset() {
+        let paging = Paging::new(
+            "select id from user",
+            &[],
+            Page {
+                max_size: 10,
+                default_size: Some(1),
+                key: Key {
+                    column: "id".to_string(),
+                    ty: Type::Int,
+                    descending: true,
+                },
+                offset: false,
+                count: true,
+            },
+        )
+        .unwrap();
+
+        let req = paging.request(&Input::default()).unwrap();
+        let page = paging.envelope(
+            &req,
+            json!([{"id": 9}, {"id": 8}]),
+            Some(json!([{"total": 2}])),
+        );
+        assert_eq!(page["total"], json!(2));
+
+        let cursor = page["next_cursor"].as_str().unwrap().to_string();
+        let req = paging
+            .request(&input(&[("cursor", cursor.as_str())]))
+            .unwrap();
+        assert_eq!(
+            paging.statement(&req).sql,
+            "select * from (select id from user) as page where id < ? order by id desc limit ?"
+        );
+        assert_eq!(
+            paging.args(&req, &Input::default(), false).unwrap(),
+            vec![Value::Int(9), Value::Int(2)]
+        );
+
+        assert!(paging.request(&input(&[("cursor", "!")])).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use super::{Backend, Format};
+use crate::operator::sql::param::{Input, Param, ParamError, Statement, Value};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use serde_json::Value as JsonValue;
+use sqlx::decode::Decode;
+use sqlx::postgres::{PgArguments, PgDone, PgTypeInfo, PgValueRef, Postgres};
+use sqlx::query::Query;
+use sqlx::types::Type;
+use sqlx::types::{BigDecimal, Uuid};
+use sqlx::{TypeInfo, ValueRef};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error("unsupported column type {0}")]
+pub struct UnsupportedType(String);
+
+// built in types sent in a binary format that have no arm in `get_as_json_value`
+const BINARY: &[&str] = &[
+    "INTERVAL",
+    "TIMETZ",
+    "MONEY",
+    "INET",
+    "CIDR",
+    "MACADDR",
+    "MACADDR8",
+    "BIT",
+    "VARBIT",
+    "POINT",
+    "LSEG",
+    "PATH",
+    "BOX",
+    "POLYGON",
+    "LINE",
+    "CIRCLE",
+    "TSVECTOR",
+    "TSQUERY",
+    "INT4RANGE",
+    "INT8RANGE",
+    "NUMRANGE",
+    "TSRANGE",
+    "TSTZRANGE",
+    "DATERANGE",
+    "JSONPATH",
+    "RECORD",
+    "TID",
+    "XID",
+    "CID",
+    "INT2VECTOR",
+    "OIDVECTOR",
+];
+
+// Args are sent with the type of the rust value (`Null` as text), so a placeholder
+// compared against another type may need a cast in the sql, e.g. `:id::uuid`.
+impl Backend for Postgres {
+    fn bind<'q>(
+        q: Query<'q, Postgres, PgArguments>,
+        value: &Value,
+    ) -> Query<'q, Postgres, PgArguments> {
+        match value.clone() {
+            Value::Null => q.bind(None::<String>),
+            Value::Int(v) => q.bind(v),
+            Value::Float(v) => q.bind(v),
+            Value::String(v) => q.bind(v),
+            Value::Bool(v) => q.bind(v),
+        }
+    }
+
+    fn get_as_json_value(
+        vref: PgValueRef<'_>,
+        type_info: &PgTypeInfo,
+        format: &Format,
+    ) -> Result<JsonValue, sqlx::Error> {
+        if vref.is_null() {
+            return Ok(JsonValue::Null);
+        }
+
+        Ok(match type_info.name() {
+            "BOOL" => JsonValue::from(decode::<bool>(vref)?),
+            "\"CHAR\"" => JsonValue::from(decode::<i8>(vref)?),
+            "INT2" => JsonValue::from(decode::<i16>(vref)?),
+            "INT4" => JsonValue::from(decode::<i32>(vref)?),
+            "INT8" => JsonValue::from(decode::<i64>(vref)?),
+            "OID" => JsonValue::from(decode::<u32>(vref)?),
+            "FLOAT4" => JsonValue::from(decode::<f32>(vref)?),
+            "FLOAT8" => JsonValue::from(decode::<f64>(vref)?),
+            "NUMERIC" => format
+                .decimal
+                .to_json(decode::<BigDecimal>(vref)?.to_string().as_str()),
+            "UUID" => JsonValue::from(decode::<Uuid>(vref)?.to_string()),
+            "JSON" | "JSONB" => decode::<JsonValue>(vref)?,
+            "BYTEA" => JsonValue::from(base64::encode(decode::<&[u8]>(vref)?)),
+            "TIMESTAMPTZ" => format
+                .datetime
+                .datetime_tz_to_json(decode::<DateTime<Utc>>(vref)?),
+            "TIMESTAMP" => format
+                .datetime
+                .datetime_to_json(decode::<NaiveDateTime>(vref)?),
+            "DATE" => format.datetime.date_to_json(decode::<NaiveDate>(vref)?),
+            "TIME" => JsonValue::from(decode::<NaiveTime>(vref)?.to_string()),
+            "BOOL[]" => array::<bool>(vref)?,
+            "INT2[]" => array::<i16>(vref)?,
+            "INT4[]" => array::<i32>(vref)?,
+            "INT8[]" => array::<i64>(vref)?,
+            "FLOAT4[]" => array::<f32>(vref)?,
+            "FLOAT8[]" => array::<f64>(vref)?,
+            "TEXT[]" | "VARCHAR[]" | "BPCHAR[]" | "NAME[]" => array::<String>(vref)?,
+            "UUID[]" => JsonValue::from(
+                decode::<Vec<Option<Uuid>>>(vref)?
+                    .into_iter()
+                    .map(|v| v.map(|v| v.to_string()))
+                    .collect::<Vec<_>>(),
+            ),
+            "NUMERIC[]" => JsonValue::from(
+                decode::<Vec<Option<BigDecimal>>>(vref)?
+                    .into_iter()
+                    .map(|v| match v {
+                        Some(v) => format.decimal.to_json(v.to_string().as_str()),
+                        None => JsonValue::Null,
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            name if name.ends_with("[]") || BINARY.contains(&name) => {
+                return Err(sqlx::Error::Decode(Box::new(UnsupportedType(
+                    name.to_string(),
+                ))))
+            }
+            // TEXT, VARCHAR, BPCHAR, NAME and the enums, whose binary format is their label
+            _ => JsonValue::from(decode::<&str>(vref)?),
+        })
+    }
+
+    // postgres has no last insert id, use `returning` in a Fetch instead
+    fn last_insert_id(_: &PgDone) -> JsonValue {
+        JsonValue::Null
+    }
+
+    fn sql(statement: &Statement) -> &str {
+        statement.numbered.as_str()
+    }
+
+    fn args(
+        statement: &Statement,
+        params: &[Param],
+        input: &Input,
+    ) -> Result<Vec<Value>, ParamError> {
+        statement.numbered_args(params, input)
+    }
+}
+
+fn decode<'r, T: Decode<'r, Postgres>>(vref: PgValueRef<'r>) -> Result<T, sqlx::Error> {
+    T::decode(vref).map_err(sqlx::Error::Decode)
+}
+
+fn array<'r, T>(vref: PgValueRef<'r>) -> Result<JsonValue, sqlx::Error>
+where
+    T: Decode<'r, Postgres> + Type<Postgres> + Into<JsonValue>,
+    Vec<Option<T>>: Decode<'r, Postgres>,
+{
+    Ok(JsonValue::from(
+        decode::<Vec<Option<T>>>(vref)?
+            .into_iter()
+            .map(|v| v.map_or(JsonValue::Null, Into::into))
+            .collect::<Vec<_>>(),
+    ))
+}
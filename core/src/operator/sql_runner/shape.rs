@@ -0,0 +1,231 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// How rows are turned into the response: every row is renamed, pruned and nested,
+/// then the rows are shaped.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Shaping {
+    #[serde(default)]
+    pub shape: Shape,
+    /// column name -> field name
+    #[serde(default)]
+    pub rename: HashMap<String, String>,
+    /// columns left out of the response, by their name before renaming
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// separator of nested fields, with `"__"` the column `author__name` becomes
+    /// `{"author": {"name": ...}}`; a nested object with only nulls, as left by an
+    /// unmatched left join, becomes null
+    #[serde(default)]
+    pub nest: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub enum Shape {
+    /// all the rows
+    Array,
+    /// the first row, not found without rows
+    Object,
+    /// the value of the first row, which must have a single column
+    Scalar,
+    /// an object of the rows keyed by the value of a field
+    Map { key: String },
+}
+
+impl Default for Shape {
+    fn default() -> Self {
+        Shape::Array
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ShapeError {
+    #[error("no rows")]
+    NotFound,
+    #[error("scalar shape needs a single column, got {0}")]
+    NotScalar(usize),
+    #[error("map key `{0}` is missing in a row")]
+    MissingKey(String),
+}
+
+impl Shaping {
+    /// The field name of `column` in the shaped rows, `None` when it is excluded.
+    pub fn field(&self, column: &str) -> Option<String> {
+        if self.exclude.iter().any(|c| c == column) {
+            return None;
+        }
+        Some(
+            self.rename
+                .get(column)
+                .cloned()
+                .unwrap_or_else(|| column.to_string()),
+        )
+    }
+
+    pub fn row(&self, row: JsonValue) -> JsonValue {
+        if self.rename.is_empty() && self.exclude.is_empty() && self.nest.is_none() {
+            return row;
+        }
+
+        let columns = match row {
+            JsonValue::Object(m) => m,
+            other => return other,
+        };
+        let mut m = Map::new();
+        let mut nested = vec![];
+        for (name, v) in columns {
+            if self.exclude.contains(&name) {
+                continue;
+            }
+            let name = self.rename.get(&name).cloned().unwrap_or(name);
+            match &self.nest {
+                Some(sep) if name.contains(sep.as_str()) => {
+                    let path: Vec<&str> = name.split(sep.as_str()).collect();
+                    nested.push(path[0].to_string());
+                    insert_path(&mut m, path, v);
+                }
+                _ => {
+                    m.insert(name, v);
+                }
+            }
+        }
+
+        for name in nested {
+            if let Some(v) = m.get_mut(&name) {
+                collapse(v);
+            }
+        }
+        JsonValue::Object(m)
+    }
+
+    pub fn shape(&self, rows: JsonValue) -> Result<JsonValue, ShapeError> {
+        let rows = match rows {
+            JsonValue::Array(rows) => rows,
+            other => return Ok(other),
+        };
+
+        match &self.shape {
+            Shape::Array => Ok(JsonValue::from(rows)),
+            Shape::Object => rows.into_iter().next().ok_or(ShapeError::NotFound),
+            Shape::Scalar => match rows.into_iter().next() {
+                Some(JsonValue::Object(row)) if row.len() == 1 => {
+                    Ok(row.into_iter().next().map(|(_, v)| v).unwrap())
+                }
+                Some(JsonValue::Object(row)) => Err(ShapeError::NotScalar(row.len())),
+                Some(other) => Ok(other),
+                None => Err(ShapeError::NotFound),
+            },
+            Shape::Map { key } => {
+                let mut m = Map::new();
+                for row in rows {
+                    let k = match row.get(key.as_str()) {
+                        Some(JsonValue::String(s)) => s.clone(),
+                        Some(v) => v.to_string(),
+                        None => return Err(ShapeError::MissingKey(key.clone())),
+                    };
+                    m.insert(k, row);
+                }
+                Ok(JsonValue::Object(m))
+            }
+        }
+    }
+}
+
+fn insert_path(m: &mut Map<String, JsonValue>, path: Vec<&str>, v: JsonValue) {
+    match path.split_first() {
+        Some((last, [])) => {
+            m.insert(last.to_string(), v);
+        }
+        Some((first, rest)) => {
+            let child = m
+                .entry(first.to_string())
+                .or_insert_with(|| JsonValue::Object(Map::new()));
+            // a plain column and a nested one with the same name, the nested one wins
+            if !child.is_object() {
+                *child = JsonValue::Object(Map::new());
+            }
+            if let JsonValue::Object(child) = child {
+                insert_path(child, rest.to_vec(), v);
+            }
+        }
+        None => {}
+    }
+}
+
+// returns whether `v` is all nulls, after replacing such nested objects with null
+fn collapse(v: &mut JsonValue) -> bool {
+    let all_null = match v {
+        JsonValue::Object(m) => m.values_mut().fold(true, |all, v| collapse(v) && all),
+        JsonValue::Null => return true,
+        _ => return false,
+    };
+    if all_null {
+        *v = JsonValue::Null;
+    }
+    all_null
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn rows() {
+        let shaping = Shaping {
+            rename: vec![("uid".to_string(), "id".to_string())]
+                .into_iter()
+                .collect(),
+            exclude: vec!["password".to_string()],
+            nest: Some("__".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            shaping.row(json!({
+                "uid": 1,
+                "password": "x",
+                "author__name": "a",
+                "author__org__id": 2,
+                "editor__name": null,
+                "editor__org__id": null,
+            })),
+            json!({"id": 1, "author": {"name": "a", "org": {"id": 2}}, "editor": null})
+        );
+    }
+
+    #[test]
+    fn shapes() {
+        let rows = json!([{"id": 1, "name": "a"}, {"id": 2, "name": "b"}]);
+        let shaping = |shape| Shaping {
+            shape,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            shaping(Shape::Object).shape(rows.clone()).unwrap(),
+            json!({"id": 1, "name": "a"})
+        );
+        assert!(matches!(
+            shaping(Shape::Object).shape(json!([])),
+            Err(ShapeError::NotFound)
+        ));
+        assert_eq!(
+            shaping(Shape::Scalar).shape(json!([{"n": 3}])).unwrap(),
+            json!(3)
+        );
+        assert!(matches!(
+            shaping(Shape::Scalar).shape(rows.clone()),
+            Err(ShapeError::NotScalar(2))
+        ));
+        assert_eq!(
+            shaping(Shape::Map {
+                key: "name".to_string()
+            })
+            .shape(rows)
+            .unwrap(),
+            json!({"a": {"id": 1, "name": "a"}, "b": {"id": 2, "name": "b"}})
+        );
+    }
+}
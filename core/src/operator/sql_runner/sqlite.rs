@@ -0,0 +1,90 @@
+use super::{Backend, Format};
+use crate::operator::sql::param::Value;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde_json::Value as JsonValue;
+use sqlx::decode::Decode;
+use sqlx::query::Query;
+use sqlx::sqlite::{Sqlite, SqliteArguments, SqliteDone, SqliteTypeInfo, SqliteValueRef};
+use sqlx::{TypeInfo, ValueRef};
+
+impl Backend for Sqlite {
+    fn bind<'q>(
+        q: Query<'q, Sqlite, SqliteArguments<'q>>,
+        value: &Value,
+    ) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+        match value.clone() {
+            Value::Null => q.bind(None::<String>),
+            Value::Int(v) => q.bind(v),
+            Value::Float(v) => q.bind(v),
+            Value::String(v) => q.bind(v),
+            Value::Bool(v) => q.bind(v),
+        }
+    }
+
+    // Sqlite is dynamically typed, so the storage class of the value decides how it is
+    // decoded and the declared column type only refines how it is rendered.
+    fn get_as_json_value(
+        vref: SqliteValueRef<'_>,
+        type_info: &SqliteTypeInfo,
+        format: &Format,
+    ) -> Result<JsonValue, sqlx::Error> {
+        if vref.is_null() {
+            return Ok(JsonValue::Null);
+        }
+
+        let storage = match vref.type_info() {
+            Some(t) => t.name().to_string(),
+            None => type_info.name().to_string(),
+        };
+
+        Ok(match (type_info.name(), storage.as_str()) {
+            ("BOOLEAN", "INTEGER") => JsonValue::from(decode::<bool>(vref)?),
+            ("DATETIME", "TEXT") => {
+                let s = decode::<&str>(vref)?;
+                match parse_datetime(s) {
+                    Some(dt) => format.datetime.datetime_to_json(dt),
+                    None => JsonValue::from(s),
+                }
+            }
+            ("DATE", "TEXT") => {
+                let s = decode::<&str>(vref)?;
+                match NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+                    Ok(d) => format.datetime.date_to_json(d),
+                    Err(_) => JsonValue::from(s),
+                }
+            }
+            ("NUMERIC", "TEXT") => format.decimal.to_json(decode::<&str>(vref)?),
+            (_, "INTEGER") => JsonValue::from(decode::<i64>(vref)?),
+            (_, "REAL") => JsonValue::from(decode::<f64>(vref)?),
+            (_, "BLOB") => JsonValue::from(base64::encode(decode::<&[u8]>(vref)?)),
+            _ => JsonValue::from(decode::<&str>(vref)?),
+        })
+    }
+
+    fn last_insert_id(done: &SqliteDone) -> JsonValue {
+        JsonValue::from(done.last_insert_rowid())
+    }
+}
+
+fn decode<'r, T: Decode<'r, Sqlite>>(vref: SqliteValueRef<'r>) -> Result<T, sqlx::Error> {
+    T::decode(vref).map_err(sqlx::Error::Decode)
+}
+
+fn parse_datetime(s: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f"))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn datetime() {
+        let dt = NaiveDate::from_ymd(2020, 1, 2).and_hms(3, 4, 5);
+        assert_eq!(parse_datetime("2020-01-02 03:04:05"), Some(dt));
+        assert_eq!(parse_datetime("2020-01-02T03:04:05"), Some(dt));
+        assert_eq!(parse_datetime("yesterday"), None);
+    }
+}
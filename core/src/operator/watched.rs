@@ -0,0 +1,57 @@
+use async_std::sync::{Arc, RwLock};
+use std::io::Result;
+use std::time::SystemTime;
+
+/// The parsed content of a file, read again whenever the file changes. A file that can't
+/// be read or parsed keeps the content read before.
+pub struct Watched<T> {
+    file: String,
+    parse: fn(&[u8]) -> Result<T>,
+    loaded: RwLock<Loaded<T>>,
+}
+
+struct Loaded<T> {
+    // modification time and length of the file read
+    version: (SystemTime, u64),
+    value: Arc<T>,
+}
+
+impl<T> Watched<T> {
+    /// Reads the file a first time, blocking as operators are made before the server runs.
+    pub fn new(file: &str, parse: fn(&[u8]) -> Result<T>) -> Result<Self> {
+        let meta = std::fs::metadata(file)?;
+        let version = (meta.modified()?, meta.len());
+        let value = parse(&std::fs::read(file)?)?;
+        Ok(Watched {
+            file: file.to_string(),
+            parse,
+            loaded: RwLock::new(Loaded {
+                version,
+                value: Arc::new(value),
+            }),
+        })
+    }
+
+    /// The content, read again first when the file changed since it was last read.
+    pub async fn get(&self) -> Arc<T> {
+        match self.changed().await {
+            Ok(None) => {}
+            Ok(Some(loaded)) => *self.loaded.write().await = loaded,
+            Err(err) => log::error!("reading {} failed: {}", self.file, err),
+        }
+        self.loaded.read().await.value.clone()
+    }
+
+    async fn changed(&self) -> Result<Option<Loaded<T>>> {
+        let meta = async_std::fs::metadata(&self.file).await?;
+        let version = (meta.modified()?, meta.len());
+        if self.loaded.read().await.version == version {
+            return Ok(None);
+        }
+        let value = (self.parse)(&async_std::fs::read(&self.file).await?)?;
+        Ok(Some(Loaded {
+            version,
+            value: Arc::new(value),
+        }))
+    }
+}
@@ -25,6 +25,9 @@ pub struct OperatorMeta {
     pub ty: &'static str,
     pub source: bool,
     pub new_async: bool,
+    /// `init` is awaited after all applies and before any source is started, the ones of
+    /// `sql_migrate` first, then each after the operators applied to it
+    pub init: bool,
 }
 
 impl VisualGraph {
@@ -35,6 +38,8 @@ impl VisualGraph {
 
         let mut sorted_applies = vec![];
         let mut operators = vec![];
+        // operators in the order they are handled, each after the ones applied to it
+        let mut sorted = vec![];
 
         let meta: HashMap<_, _> = vec![
             (
@@ -44,6 +49,27 @@ impl VisualGraph {
                     ty: "Sql::<::sqlx::mysql::MySql>",
                     source: false,
                     new_async: true,
+                    init: false,
+                },
+            ),
+            (
+                "sqlite",
+                OperatorMeta {
+                    file: "sql",
+                    ty: "Sql::<::sqlx::sqlite::Sqlite>",
+                    source: false,
+                    new_async: true,
+                    init: false,
+                },
+            ),
+            (
+                "postgres",
+                OperatorMeta {
+                    file: "sql",
+                    ty: "Sql::<::sqlx::postgres::Postgres>",
+                    source: false,
+                    new_async: true,
+                    init: false,
                 },
             ),
             (
@@ -53,6 +79,137 @@ impl VisualGraph {
                     ty: "SqlRunner",
                     source: false,
                     new_async: false,
+                    init: false,
+                },
+            ),
+            (
+                "sql_transaction",
+                OperatorMeta {
+                    file: "sql_transaction",
+                    ty: "SqlTransaction",
+                    source: false,
+                    new_async: false,
+                    init: false,
+                },
+            ),
+            (
+                "cache",
+                OperatorMeta {
+                    file: "cache",
+                    ty: "Cache",
+                    source: false,
+                    new_async: false,
+                    init: false,
+                },
+            ),
+            (
+                "cache_invalidator",
+                OperatorMeta {
+                    file: "cache_invalidator",
+                    ty: "CacheInvalidator",
+                    source: false,
+                    new_async: false,
+                    init: false,
+                },
+            ),
+            (
+                "sql_migrate",
+                OperatorMeta {
+                    file: "sql_migrate",
+                    ty: "SqlMigrate",
+                    source: false,
+                    new_async: false,
+                    init: true,
+                },
+            ),
+            (
+                "sql_crud",
+                OperatorMeta {
+                    file: "sql_crud",
+                    ty: "SqlCrud",
+                    source: false,
+                    new_async: false,
+                    init: true,
+                },
+            ),
+            (
+                "sql_query",
+                OperatorMeta {
+                    file: "sql_query",
+                    ty: "SqlQuery",
+                    source: false,
+                    new_async: false,
+                    init: false,
+                },
+            ),
+            (
+                "sql_poller",
+                OperatorMeta {
+                    file: "sql_poller",
+                    ty: "SqlPoller",
+                    source: true,
+                    new_async: false,
+                    init: false,
+                },
+            ),
+            (
+                "sql_import",
+                OperatorMeta {
+                    file: "sql_import",
+                    ty: "SqlImport",
+                    source: false,
+                    new_async: false,
+                    init: false,
+                },
+            ),
+            (
+                "jwt_auth",
+                OperatorMeta {
+                    file: "jwt_auth",
+                    ty: "JwtAuth",
+                    source: false,
+                    new_async: false,
+                    init: false,
+                },
+            ),
+            (
+                "api_key",
+                OperatorMeta {
+                    file: "api_key",
+                    ty: "ApiKey",
+                    source: true,
+                    new_async: false,
+                    init: true,
+                },
+            ),
+            (
+                "basic_auth",
+                OperatorMeta {
+                    file: "basic_auth",
+                    ty: "BasicAuth",
+                    source: false,
+                    new_async: false,
+                    init: false,
+                },
+            ),
+            (
+                "rbac",
+                OperatorMeta {
+                    file: "rbac",
+                    ty: "Rbac",
+                    source: false,
+                    new_async: false,
+                    init: false,
+                },
+            ),
+            (
+                "tenant_router",
+                OperatorMeta {
+                    file: "tenant_router",
+                    ty: "TenantRouter",
+                    source: false,
+                    new_async: false,
+                    init: false,
                 },
             ),
             (
@@ -62,6 +219,7 @@ impl VisualGraph {
                     ty: "HTTPAPI",
                     source: false,
                     new_async: false,
+                    init: false,
                 },
             ),
             (
@@ -71,6 +229,7 @@ impl VisualGraph {
                     ty: "HTTPServer",
                     source: true,
                     new_async: false,
+                    init: false,
                 },
             ),
             (
@@ -80,6 +239,7 @@ impl VisualGraph {
                     ty: "Wasm",
                     source: true,
                     new_async: false,
+                    init: false,
                 },
             ),
             (
@@ -89,6 +249,7 @@ impl VisualGraph {
                     ty: "SimpleAuth",
                     source: false,
                     new_async: false,
+                    init: false,
                 },
             ),
             (
@@ -98,6 +259,7 @@ impl VisualGraph {
                     ty: "SagaAggregator",
                     source: false,
                     new_async: true,
+                    init: false,
                 },
             ),
         ]
@@ -112,6 +274,7 @@ impl VisualGraph {
             edgeto: &mut Vec<isize>,
             onstack: &mut Vec<bool>,
             sorted_applies: &mut Vec<Apply>,
+            sorted: &mut Vec<usize>,
         ) {
             unsafe {
                 if *handled.get_unchecked(i) {
@@ -148,7 +311,15 @@ impl VisualGraph {
                                 panic!(println!("cycle found:{:?}", cycle));
                             } else {
                                 *edgeto.get_unchecked_mut(from) = i as isize;
-                                handle_one(from, vg, handled, edgeto, onstack, sorted_applies);
+                                handle_one(
+                                    from,
+                                    vg,
+                                    handled,
+                                    edgeto,
+                                    onstack,
+                                    sorted_applies,
+                                    sorted,
+                                );
 
                                 sorted_applies.push(Apply { to: i, from: from });
                             }
@@ -159,6 +330,7 @@ impl VisualGraph {
 
                 *onstack.get_unchecked_mut(i) = false;
                 *handled.get_unchecked_mut(i) = true;
+                sorted.push(i);
             }
         }
 
@@ -171,6 +343,7 @@ impl VisualGraph {
                     &mut edgeto,
                     &mut onstack,
                     &mut sorted_applies,
+                    &mut sorted,
                 );
             }
             operators.push(Operator {
@@ -179,9 +352,17 @@ impl VisualGraph {
             });
         }
 
+        // migrations create the schema the other inits read, so they go first
+        let (mut inits, rest): (Vec<usize>, Vec<usize>) = sorted
+            .into_iter()
+            .filter(|i| operators[*i].meta.init)
+            .partition(|i| operators[*i].meta.file == "sql_migrate");
+        inits.extend(rest);
+
         Graph {
             operators,
             sorted_applies,
+            inits,
         }
     }
 }
@@ -195,4 +376,6 @@ pub struct Apply {
 pub struct Graph<'a> {
     pub operators: Vec<Operator<'a>>,
     pub sorted_applies: Vec<Apply>,
+    /// the operators to `init`, in order
+    pub inits: Vec<usize>,
 }
@@ -1,4 +1,5 @@
 
+core::logger::init();
 
 {% for op in operators %}
 let config{{loop.index0}} = serde_json::from_str({{op.config|json_encode|json_encode}}).unwrap();
@@ -13,6 +14,12 @@ let mut op{{loop.index0}} = Arc::new({{op.meta.file}}::{{op.meta.ty}}::new(confi
 Arc::get_mut(&mut op{{apply.to}}).unwrap().apply(Arc::downgrade(&op{{apply.from}}));
 {% endfor %}
 
+{% for i in inits %}
+    if let Err(err) = op{{i}}.init().await {
+        panic!("init faild: {:?}", err);
+    }
+{% endfor %}
+
 let mut handles = vec![];
 
 {% for op in operators %}